@@ -0,0 +1,11 @@
+use mogglo::cli::Lang;
+
+/// The C language
+pub fn lang() -> Lang {
+    Lang {
+        name: "c",
+        language: tree_sitter_c::language(),
+        node_types: tree_sitter_c::NODE_TYPES,
+        extensions: &["c", "h"],
+    }
+}
@@ -0,0 +1,33 @@
+use anyhow::Result;
+
+use mogglo::cli;
+
+fn main() -> Result<()> {
+    // Languages are features, so that executables can leave out grammars that
+    // don't build on some targets
+    let langs = [
+        #[cfg(feature = "c")]
+        mogglo_c::lang(),
+        #[cfg(feature = "cpp")]
+        mogglo_cpp::lang(),
+        #[cfg(feature = "css")]
+        mogglo_css::lang(),
+        #[cfg(feature = "html")]
+        mogglo_html::lang(),
+        #[cfg(feature = "java")]
+        mogglo_java::lang(),
+        #[cfg(feature = "javascript")]
+        mogglo_javascript::lang(),
+        #[cfg(feature = "python")]
+        mogglo_python::lang(),
+        #[cfg(feature = "ruby")]
+        mogglo_ruby::lang(),
+        #[cfg(feature = "rust")]
+        mogglo_rust::lang(),
+        #[cfg(feature = "swift")]
+        mogglo_swift::lang(),
+        #[cfg(feature = "typescript")]
+        mogglo_typescript::lang(),
+    ];
+    cli::run(&langs)
+}
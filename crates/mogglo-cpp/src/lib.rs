@@ -0,0 +1,11 @@
+use mogglo::cli::Lang;
+
+/// The C++ language
+pub fn lang() -> Lang {
+    Lang {
+        name: "cpp",
+        language: tree_sitter_cpp::language(),
+        node_types: tree_sitter_cpp::NODE_TYPES,
+        extensions: &["cc", "cpp", "cxx", "hh", "hpp", "hxx"],
+    }
+}
@@ -0,0 +1,11 @@
+use mogglo::cli::Lang;
+
+/// The CSS language
+pub fn lang() -> Lang {
+    Lang {
+        name: "css",
+        language: tree_sitter_css::language(),
+        node_types: tree_sitter_css::NODE_TYPES,
+        extensions: &["css"],
+    }
+}
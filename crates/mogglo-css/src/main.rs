@@ -3,5 +3,5 @@ use anyhow::Result;
 use mogglo::cli;
 
 fn main() -> Result<()> {
-    cli::main(tree_sitter_css::language(), tree_sitter_css::NODE_TYPES)
+    cli::main(mogglo_css::lang())
 }
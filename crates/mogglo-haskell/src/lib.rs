@@ -0,0 +1,11 @@
+use mogglo::cli::Lang;
+
+/// The Haskell language
+pub fn lang() -> Lang {
+    Lang {
+        name: "haskell",
+        language: tree_sitter_haskell::language(),
+        node_types: tree_sitter_haskell::NODE_TYPES,
+        extensions: &["hs"],
+    }
+}
@@ -0,0 +1,11 @@
+use mogglo::cli::Lang;
+
+/// The HTML language
+pub fn lang() -> Lang {
+    Lang {
+        name: "html",
+        language: tree_sitter_html::language(),
+        node_types: tree_sitter_html::NODE_TYPES,
+        extensions: &["htm", "html"],
+    }
+}
@@ -3,5 +3,5 @@ use anyhow::Result;
 use mogglo::cli;
 
 fn main() -> Result<()> {
-    cli::main(tree_sitter_html::language(), tree_sitter_html::NODE_TYPES)
+    cli::main(mogglo_html::lang())
 }
@@ -0,0 +1,11 @@
+use mogglo::cli::Lang;
+
+/// The Java language
+pub fn lang() -> Lang {
+    Lang {
+        name: "java",
+        language: tree_sitter_java::language(),
+        node_types: tree_sitter_java::NODE_TYPES,
+        extensions: &["java"],
+    }
+}
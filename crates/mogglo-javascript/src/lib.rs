@@ -0,0 +1,11 @@
+use mogglo::cli::Lang;
+
+/// The JavaScript language
+pub fn lang() -> Lang {
+    Lang {
+        name: "javascript",
+        language: tree_sitter_javascript::language(),
+        node_types: tree_sitter_javascript::NODE_TYPES,
+        extensions: &["cjs", "js", "jsx", "mjs"],
+    }
+}
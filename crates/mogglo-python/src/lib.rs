@@ -0,0 +1,11 @@
+use mogglo::cli::Lang;
+
+/// The Python language
+pub fn lang() -> Lang {
+    Lang {
+        name: "python",
+        language: tree_sitter_python::language(),
+        node_types: tree_sitter_python::NODE_TYPES,
+        extensions: &["py", "pyi"],
+    }
+}
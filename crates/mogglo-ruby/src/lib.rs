@@ -0,0 +1,11 @@
+use mogglo::cli::Lang;
+
+/// The Ruby language
+pub fn lang() -> Lang {
+    Lang {
+        name: "ruby",
+        language: tree_sitter_ruby::language(),
+        node_types: tree_sitter_ruby::NODE_TYPES,
+        extensions: &["rb"],
+    }
+}
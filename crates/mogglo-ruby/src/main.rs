@@ -3,5 +3,5 @@ use anyhow::Result;
 use mogglo::cli;
 
 fn main() -> Result<()> {
-    cli::main(tree_sitter_ruby::language(), tree_sitter_ruby::NODE_TYPES)
+    cli::main(mogglo_ruby::lang())
 }
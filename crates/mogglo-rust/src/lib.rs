@@ -0,0 +1,11 @@
+use mogglo::cli::Lang;
+
+/// The Rust language
+pub fn lang() -> Lang {
+    Lang {
+        name: "rust",
+        language: tree_sitter_rust::language(),
+        node_types: tree_sitter_rust::NODE_TYPES,
+        extensions: &["rs"],
+    }
+}
@@ -0,0 +1,11 @@
+use mogglo::cli::Lang;
+
+/// The Swift language
+pub fn lang() -> Lang {
+    Lang {
+        name: "swift",
+        language: tree_sitter_swift::language(),
+        node_types: tree_sitter_swift::NODE_TYPES,
+        extensions: &["swift"],
+    }
+}
@@ -0,0 +1,11 @@
+use mogglo::cli::Lang;
+
+/// The TypeScript language
+pub fn lang() -> Lang {
+    Lang {
+        name: "typescript",
+        language: tree_sitter_typescript::language_typescript(),
+        node_types: tree_sitter_typescript::TYPESCRIPT_NODE_TYPES,
+        extensions: &["cts", "mts", "ts"],
+    }
+}
@@ -1,44 +1,138 @@
 use std::{
+    collections::{BTreeMap, HashMap},
+    ffi::OsStr,
     fs,
-    io::{self, Read},
+    io::{self, Read, Write},
     ops::Range,
+    path::{Component, Path, PathBuf},
     process,
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        mpsc,
+    },
+    thread,
 };
 
-use anyhow::{Context, Result};
+use anyhow::{bail, Context, Result};
 use ariadne::{Color, ColorGenerator, Fmt, Label, Report, ReportKind, Source};
-use clap::Parser;
+use clap::{error::ErrorKind, ArgGroup, CommandFactory, Parser};
+use ignore::WalkBuilder;
+use similar::TextDiff;
 use tree_sitter::{Language, Tree};
 
+mod json;
+mod sarif;
+mod test;
+mod trace;
+
 use crate::{
+    dump::dump,
     env::Env,
+    lua::{check_kinds, LuaState},
     node_types::NodeTypes,
-    pattern::{LuaCode, Pattern},
+    pattern::{Match, ParseContext, Pattern, PatternError},
+    rule::{Rule, Severity},
 };
 
 /// A multi-language AST-based code search and rewriting (codemod) tool
 #[derive(Debug, Parser)]
 #[command(author, version, about, long_about = None)]
+#[command(args_conflicts_with_subcommands = true, subcommand_negates_reqs = true)]
+#[command(group(ArgGroup::new("replacements").args(["replace", "rules"]).multiple(true)))]
 pub struct Args {
-    /// Confirm before each replacement
+    #[command(subcommand)]
+    pub command: Option<Command>,
+
+    /// Run even if patterns don't parse or refer to kinds of nodes that don't
+    /// exist
+    #[arg(long)]
+    pub allow_pattern_errors: bool,
+
+    /// Confirm before each replacement (implies `--jobs 1`)
     #[arg(long)]
     pub confirm: bool,
 
+    /// Code to parse the pattern and replacement in, with `$PAT` where they
+    /// go, e.g., `--context 'match x { $PAT }'`
+    #[arg(long, value_name = "CODE", conflicts_with = "rules")]
+    pub context: Option<String>,
+
     /// Show details
     #[arg(long)]
     pub detail: bool,
 
+    /// Print a unified diff of the replacements in each file, don't perform
+    /// them
+    #[arg(
+        long,
+        requires = "replacements",
+        conflicts_with_all = ["confirm", "format", "only_matching"]
+    )]
+    pub diff: bool,
+
+    /// Only report matches that contain a node matching this pattern
+    #[arg(long, value_name = "PATTERN", conflicts_with = "rules")]
+    pub has: Vec<String>,
+
+    /// Search hidden files and directories
+    #[arg(long)]
+    pub hidden: bool,
+
     /// Print replacements, don't perform them
     #[arg(short, long)]
     pub dry_run: bool,
 
+    /// Print the syntax tree of each file, with the kind, field, and byte
+    /// range of each node, don't search
+    #[arg(long, conflicts_with_all = ["rules", "explain"])]
+    pub dump_ast: bool,
+
+    /// Print how the patterns were parsed, don't search
+    #[arg(long)]
+    pub explain: bool,
+
+    /// Output format
+    #[arg(
+        long,
+        default_value_t = OutputFormat::Text,
+        value_name = "FORMAT",
+        conflicts_with = "confirm"
+    )]
+    pub format: OutputFormat,
+
+    /// Only report matches inside a node matching this pattern
+    #[arg(long, value_name = "PATTERN", conflicts_with = "rules")]
+    pub inside: Vec<String>,
+
+    /// Kind of node in the parsed pattern to match, e.g., `match_arm`
+    #[arg(long, conflicts_with = "rules")]
+    pub kind: Option<String>,
+
+    /// Language of the pattern and files. By default, the language of each
+    /// file is the one with its extension.
+    #[arg(long)]
+    pub lang: Option<String>,
+
     /// Limit to this number of matches per file
     #[arg(long)]
     limit: Option<usize>,
 
-    // Number of threads (TODO)
-    // #[arg(short, long, default_value_t = num_cpus::get())]
-    // pub jobs: usize,
+    /// Don't respect ignore files (`.gitignore`, `.ignore`, etc.)
+    #[arg(long)]
+    pub no_ignore: bool,
+
+    /// Only report matches that don't contain a node matching this pattern
+    #[arg(long, value_name = "PATTERN", conflicts_with = "rules")]
+    pub not_has: Vec<String>,
+
+    /// Only report matches that aren't inside a node matching this pattern
+    #[arg(long, value_name = "PATTERN", conflicts_with = "rules")]
+    pub not_inside: Vec<String>,
+
+    /// Number of threads
+    #[arg(short, long, default_value_t = num_cpus::get())]
+    pub jobs: usize,
+
     /// Behavior on parse errors
     #[arg(long, default_value_t = OnParseError::Ignore, value_name = "CHOICE")]
     on_parse_error: OnParseError,
@@ -47,27 +141,162 @@ pub struct Args {
     #[arg(long)]
     pub only_matching: bool,
 
+    /// Another pattern to search for, matches of either pattern are reported
+    #[arg(long, value_name = "PATTERN", conflicts_with = "rules")]
+    pub or: Vec<String>,
+
     /// Recursively match patterns
     #[arg(long)]
     pub recursive: bool,
 
     /// Pattern to replace with
-    #[arg(short, long)]
+    #[arg(short, long, conflicts_with = "rules")]
     pub replace: Option<String>,
 
+    /// Run the rules in a rule file, or in all rule files in a directory
+    #[arg(long, value_name = "PATH")]
+    pub rules: Vec<PathBuf>,
+
+    /// Require the children of matched nodes to match the pattern exactly,
+    /// with no extra siblings
+    #[arg(long, conflicts_with = "rules")]
+    pub strict: bool,
+
+    /// Print the decisions made while matching at a position in the file,
+    /// `LINE:COLUMN` or a byte range `START..END`, and where matching failed
+    #[arg(long, value_name = "POS", conflicts_with_all = ["dump_ast", "explain"])]
+    pub trace: Option<trace::Position>,
+
     /// Additional conditions on the match
-    #[arg(short, long, value_name = "LUA")]
+    #[arg(short, long, value_name = "LUA", conflicts_with = "rules")]
     pub r#where: Vec<String>,
 
-    /// Pattern to search for, see the guide for details on pattern syntax
-    #[arg()]
-    pub pattern: String,
+    /// Pattern to search for, see the guide for details on pattern syntax.
+    /// Omitted when using `--rules`.
+    #[arg(required_unless_present_any = ["rules", "dump_ast"])]
+    pub pattern: Option<String>,
 
-    /// Input files, use `-` to pass a single file on stdin
-    #[arg(value_name = "FILE", required = true, num_args = 1..)]
+    /// Input files or directories, use `-` to pass a single file on stdin
+    #[arg(value_name = "FILE", num_args = 0..)]
     pub files: Vec<String>,
 }
 
+#[derive(Debug, clap::Subcommand)]
+pub enum Command {
+    /// Check that rules match their `invalid` examples but not their `valid`
+    /// ones, and that their replacements produce the `fixed` code
+    Test {
+        /// Rule files or directories of rule files
+        #[arg(value_name = "PATH", required = true, num_args = 1..)]
+        rules: Vec<PathBuf>,
+    },
+}
+
+/// A language supported by the CLI
+#[derive(Clone, Copy, Debug)]
+pub struct Lang {
+    /// Name of the language, as used in rule files
+    pub name: &'static str,
+    pub language: Language,
+    /// Contents of the grammar's `node-types.json`
+    pub node_types: &'static str,
+    /// Extensions of files in this language, without the leading `.`
+    pub extensions: &'static [&'static str],
+}
+
+/// Exit with a usage error
+fn usage_error(bin: &OsStr, kind: ErrorKind, message: impl std::fmt::Display) -> ! {
+    Args::command()
+        .bin_name(bin.to_string_lossy())
+        .error(kind, message)
+        .exit()
+}
+
+/// The languages to use: The one named by `--lang`, or else all of them
+fn select_langs<'l>(args: &Args, langs: &'l [Lang], bin: &OsStr) -> Vec<&'l Lang> {
+    let Some(name) = &args.lang else {
+        return langs.iter().collect();
+    };
+    match langs.iter().find(|l| l.name == name) {
+        Some(lang) => vec![lang],
+        None => {
+            let names: Vec<_> = langs.iter().map(|l| l.name).collect();
+            usage_error(
+                bin,
+                ErrorKind::InvalidValue,
+                format!(
+                    "unknown language {name}, expected one of: {}",
+                    names.join(", ")
+                ),
+            )
+        }
+    }
+}
+
+/// The files to search (see [`walk`]), in order, each with its language. With
+/// a single language, all files are in it. Otherwise, each file is in the
+/// language with its extension.
+fn lang_files<'l>(args: &Args, langs: &[&'l Lang], bin: &OsStr) -> Result<Vec<(&'l Lang, String)>> {
+    let extensions: Vec<_> = langs.iter().flat_map(|l| l.extensions).copied().collect();
+    let mut files = Vec::new();
+    for f in walk(args, &extensions)? {
+        let lang = if let [lang] = langs {
+            lang
+        } else {
+            let ext = Path::new(&f).extension().and_then(|e| e.to_str());
+            let lang = langs
+                .iter()
+                .find(|l| ext.is_some_and(|e| l.extensions.contains(&e)));
+            match lang {
+                Some(lang) => lang,
+                None => usage_error(
+                    bin,
+                    ErrorKind::InvalidValue,
+                    format!("can't tell the language of {f}, pass --lang"),
+                ),
+            }
+        };
+        files.push((*lang, f));
+    }
+    Ok(files)
+}
+
+/// Expand directories into the files they contain that have one of the given
+/// extensions. Explicitly-named files are kept regardless of their extension.
+fn walk(args: &Args, extensions: &[&str]) -> Result<Vec<String>> {
+    let mut files = Vec::new();
+    for f in &args.files {
+        if f == "-" || !Path::new(f).is_dir() {
+            files.push(f.clone());
+            continue;
+        }
+        let walker = WalkBuilder::new(f)
+            .standard_filters(!args.no_ignore)
+            .hidden(!args.hidden)
+            .sort_by_file_name(|a, b| a.cmp(b))
+            .build();
+        for entry in walker {
+            // Like ripgrep, skip what can't be read instead of giving up
+            let entry = match entry {
+                Ok(entry) => entry,
+                Err(e) => {
+                    eprintln!("[WARN] {e}");
+                    continue;
+                }
+            };
+            if !entry.file_type().is_some_and(|t| t.is_file()) {
+                continue;
+            }
+            let path = entry.path();
+            let ext = path.extension().and_then(|e| e.to_str());
+            if ext.is_some_and(|e| extensions.contains(&e)) {
+                files.push(path.to_string_lossy().into_owned());
+            }
+        }
+    }
+    Ok(files)
+}
+
 fn read_file(file: &str) -> Result<String> {
     fs::read_to_string(file).with_context(|| format!("Failed to read file {}", file))
 }
@@ -103,24 +332,62 @@ impl Default for OnParseError {
     }
 }
 
-fn handle_parse_errors(path: &str, tree: &Tree, on_parse_error: &OnParseError) {
+#[derive(Debug, Clone, PartialEq, Eq, clap::ValueEnum)]
+pub enum OutputFormat {
+    /// Human-readable reports
+    Text,
+    /// A JSON array of matches
+    Json,
+    /// One JSON object per match, per line
+    Jsonl,
+    /// A SARIF 2.1.0 log
+    Sarif,
+}
+
+impl std::fmt::Display for OutputFormat {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            OutputFormat::Text => write!(f, "text"),
+            OutputFormat::Json => write!(f, "json"),
+            OutputFormat::Jsonl => write!(f, "jsonl"),
+            OutputFormat::Sarif => write!(f, "sarif"),
+        }
+    }
+}
+
+#[allow(clippy::derivable_impls)]
+impl Default for OutputFormat {
+    fn default() -> Self {
+        OutputFormat::Text
+    }
+}
+
+/// Returns whether to stop processing files
+fn handle_parse_errors(
+    path: &str,
+    tree: &Tree,
+    on_parse_error: &OnParseError,
+    err: &mut impl Write,
+) -> Result<bool> {
     let node = tree.root_node();
     match on_parse_error {
         OnParseError::Ignore => (),
         OnParseError::Warn if !node.has_error() => (),
         OnParseError::Error if !node.has_error() => (),
         OnParseError::Warn => {
-            eprintln!("[WARN] Parse error in {}", path);
+            writeln!(err, "[WARN] Parse error in {}", path)?;
         }
         OnParseError::Error => {
-            eprintln!("[ERROR] Parse error in {}", path);
-            process::exit(1);
+            writeln!(err, "[ERROR] Parse error in {}", path)?;
+            return Ok(true);
         }
     }
+    Ok(false)
 }
 
 #[allow(clippy::too_many_arguments)]
 fn match_report(
+    out: &mut (impl Write + ?Sized),
     title: &str,
     path: &str,
     text: &str,
@@ -160,6 +427,16 @@ fn match_report(
                 }
             }
         }
+        for mvar in env.1.keys() {
+            if let Some(range) = env.siblings_range(mvar) {
+                let color = colors.next();
+                builder = builder.with_label(
+                    Label::new((path, range))
+                        .with_message(format!("$..{}", mvar.0).fg(color))
+                        .with_color(color),
+                );
+            }
+        }
         for (mvar, color) in multiple {
             builder = builder.with_note(format!(
                 "Multiple occurrences of {} were structurally equal",
@@ -167,105 +444,717 @@ fn match_report(
             ))
         }
     }
-    builder.finish().print((path, Source::from(&text)))?;
+    builder
+        .finish()
+        .write((path, Source::from(&text)), &mut *out)?;
     Ok(())
 }
 
-pub fn main(language: Language, node_types_json_str: &'static str) -> Result<()> {
-    let args = Args::parse();
-    let node_types = NodeTypes::new(node_types_json_str)
-        .expect("Internal error: failed to parse node_types.json");
+/// Report problems with the patterns of the rules (see [`Pattern::errors`]),
+/// and return whether there were any. Replacements needn't be valid code, so
+/// parse errors in them are only warnings. When searching in several
+/// languages, reports on patterns from the command line name the language.
+fn check_patterns(
+    rules: &[Rule],
+    language: Language,
+    node_types: &NodeTypes,
+    from_files: bool,
+    several_languages: bool,
+    err: &mut impl Write,
+) -> Result<bool> {
+    let mut failed = false;
+    for rule in rules {
+        let ctx = rule.parse_context();
+        let parse = |p: &str, ctx| {
+            Pattern::parse_in(language, node_types, p.to_string(), ctx)
+                .errors()
+                .to_vec()
+        };
+        let mut checked: Vec<(&str, &str, Vec<PatternError>)> =
+            vec![("pattern", &rule.pattern, parse(&rule.pattern, ctx))];
+        checked.extend(rule.or.iter().map(|p| ("or", p.as_str(), parse(p, ctx))));
+        for (name, pats) in [
+            ("inside", &rule.inside),
+            ("not-inside", &rule.not_inside),
+            ("has", &rule.has),
+            ("not-has", &rule.not_has),
+        ] {
+            let default = ParseContext::default();
+            checked.extend(pats.iter().map(|p| (name, p.as_str(), parse(p, default))));
+        }
+        for w in &rule.r#where {
+            checked.push(("where", w, check_kinds(node_types, w)));
+        }
+        if let Some(r) = &rule.replace {
+            checked.push(("replace", r, parse(r, ctx)));
+        }
 
-    let mut pat = Pattern::parse(language, &node_types, args.pattern.clone());
-    pat.r#where(&mut args.r#where.into_iter().map(LuaCode));
+        for (name, source, errors) in checked {
+            let id = if from_files {
+                format!("{} ({name})", rule.id)
+            } else if several_languages {
+                format!("{name} ({})", rule.language)
+            } else {
+                name.to_string()
+            };
+            for e in errors {
+                let warn = name == "replace" && e.syntax;
+                failed |= !warn;
+                let kind = if warn {
+                    ReportKind::Warning
+                } else {
+                    ReportKind::Error
+                };
+                let color = if warn { Color::Yellow } else { Color::Red };
+                let mut builder = Report::build(kind, id.as_str(), e.span.start)
+                    .with_message(&e.message)
+                    .with_label(Label::new((id.as_str(), e.span.clone())).with_color(color));
+                if let Some(help) = &e.help {
+                    builder = builder.with_help(help);
+                }
+                builder
+                    .finish()
+                    .write((id.as_str(), Source::from(source)), &mut *err)?;
+            }
+        }
+    }
+    Ok(failed)
+}
 
-    // TODO: Parallelize
-    for f in &args.files {
-        let (tree, mut text) = if f == "-" {
-            let text = stdin_string()?;
-            let tree = crate::pattern::parse(language, &text);
-            (tree, text)
+/// Print the syntax tree of each file, see [`dump`]
+fn dump_ast(files: &[(&Lang, String)], out: &mut impl Write) -> Result<()> {
+    for (lang, f) in files {
+        let text = if f == "-" {
+            stdin_string()?
         } else {
-            let text = read_file(f)?;
-            let tree = crate::pattern::parse(language, &text);
-            (tree, text)
+            read_file(f)?
         };
-        handle_parse_errors(f, &tree, &args.on_parse_error);
-        let text0 = text.clone();
-        // When doing multiple replacements for the same pattern, the earlier
-        // ones affect the offset of the later ones.
-        let mut offset: isize = 0;
-        let mut matches = pat.matches(&tree, &text0, &Env::default(), args.recursive, args.limit);
-        matches.sort_unstable_by_key(|m| m.root.start_byte());
-        for m in matches {
-            if let Some(replace) = &args.replace {
-                let p = Pattern::parse(language, &node_types, replace.to_string());
-                // TODO: Computes replacement twice...
-                let replacement = p.replacement(&m, &text);
-                let (start, end) = p.replace(m.clone(), &mut text, offset);
-                let match_size = isize::try_from(end - start).unwrap();
-                let replacement_size = isize::try_from(replacement.len()).unwrap();
-                offset += replacement_size - match_size;
-
-                if args.only_matching {
-                    // TODO: Don't print whole text here...?
-                    println!("{}", text);
-                    continue;
-                }
-                match_report(
-                    if args.dry_run {
-                        "Would replace"
-                    } else {
-                        "Replacing"
-                    },
-                    f,
-                    &text0,
-                    m.root.byte_range(),
-                    &args.pattern,
-                    &m.env,
-                    args.detail,
-                    "Match",
-                )?;
-                let new_range = start..start + replacement.len();
-                match_report(
-                    "With",
-                    f,
-                    &text,
-                    new_range.clone(),
-                    &args.pattern,
-                    &Env::default(),
-                    args.detail,
-                    "Replacement",
+        let tree = crate::pattern::parse(lang.language, &text);
+        if files.len() > 1 {
+            writeln!(out, "==> {f} <==")?;
+        }
+        write!(out, "{}", dump(tree.root_node(), &text))?;
+    }
+    Ok(())
+}
+
+/// Print how the patterns and replacements of the rules were parsed, see
+/// [`Pattern::explain`]
+fn explain(
+    rules: &[Rule],
+    language: Language,
+    node_types: &NodeTypes,
+    from_files: bool,
+    out: &mut impl Write,
+) -> Result<()> {
+    for rule in rules {
+        if from_files {
+            writeln!(out, "# {}", rule.id)?;
+        }
+        let ctx = rule.parse_context();
+        let mut pats = vec![("pattern", &rule.pattern)];
+        pats.extend(rule.or.iter().map(|p| ("or", p)));
+        pats.extend(rule.replace.iter().map(|r| ("replace", r)));
+        for (name, p) in pats {
+            let pat = Pattern::parse_in(language, node_types, p.to_string(), ctx);
+            writeln!(out, "{name}: {p}")?;
+            write!(out, "{}", pat.explain())?;
+        }
+    }
+    Ok(())
+}
+
+/// A rule, with its patterns parsed
+struct Check<'r, 'nts> {
+    rule: &'r Rule,
+    pat: Pattern<'nts>,
+    replace: Option<Pattern<'nts>>,
+}
+
+/// A language, with its node types and the rules to search for in it
+type Search<'l> = (&'l Lang, NodeTypes<'static>, Vec<Rule>);
+
+/// The checks for each of the searches
+fn all_checks<'s>(searches: &'s [Search]) -> Vec<Vec<Check<'s, 's>>> {
+    searches
+        .iter()
+        .map(|(lang, node_types, rules)| checks(rules, lang.language, node_types))
+        .collect()
+}
+
+fn checks<'r, 'nts>(
+    rules: &'r [Rule],
+    language: Language,
+    node_types: &'nts NodeTypes<'nts>,
+) -> Vec<Check<'r, 'nts>> {
+    rules
+        .iter()
+        .map(|rule| Check {
+            rule,
+            pat: rule.pattern(language, node_types),
+            replace: rule.replacement(language, node_types),
+        })
+        .collect()
+}
+
+/// The rules to run: Those from `--rules` for this language, or else one made
+/// from the pattern, `--where`, and `--replace`
+fn rules(args: &Args, lang: &Lang, loaded: &[Rule]) -> Vec<Rule> {
+    if args.rules.is_empty() {
+        let pattern = args.pattern.clone().unwrap_or_default();
+        // Not the pattern itself, which would change the id (e.g., in SARIF
+        // logs) whenever the pattern is edited
+        return vec![Rule {
+            id: "pattern-0".to_string(),
+            language: lang.name.to_string(),
+            pattern: pattern.clone(),
+            or: args.or.clone(),
+            context: args.context.clone(),
+            kind: args.kind.clone(),
+            r#where: args.r#where.clone(),
+            inside: args.inside.clone(),
+            not_inside: args.not_inside.clone(),
+            has: args.has.clone(),
+            not_has: args.not_has.clone(),
+            replace: args.replace.clone(),
+            strict: args.strict,
+            severity: Severity::Warning,
+            message: pattern,
+            valid: Vec::new(),
+            invalid: Vec::new(),
+        }];
+    }
+    loaded
+        .iter()
+        .filter(|r| r.language == lang.name)
+        .cloned()
+        .collect()
+}
+
+/// Load the rules in the given files or directories. Rules for languages that
+/// aren't among `langs` are errors, rather than never running. Rules can share
+/// an id (e.g., for different languages), but only if they have the same
+/// message and severity, as reports (e.g., SARIF logs) describe each id once.
+fn load_rules(paths: &[PathBuf], langs: &[Lang]) -> Result<Vec<Rule>> {
+    let names: Vec<_> = langs.iter().map(|l| l.name).collect();
+    let mut rules = Vec::new();
+    for path in paths {
+        rules.extend(Rule::load(path, &names)?);
+    }
+    let mut ids = HashMap::new();
+    for rule in &rules {
+        let described = (&rule.message, rule.severity);
+        if *ids.entry(&rule.id).or_insert(described) != described {
+            bail!(
+                "Rules with the id {} have different messages or severities",
+                rule.id
+            );
+        }
+    }
+    Ok(rules)
+}
+
+/// Write a match in a machine-readable format. Returns `false` for
+/// `--format text`, which is handled by the caller.
+fn write_record(
+    args: &Args,
+    rule: &Rule,
+    out: &mut (impl Write + ?Sized),
+    path: &str,
+    text: &str,
+    m: &Match,
+    replacement: Option<&str>,
+) -> Result<bool> {
+    match args.format {
+        OutputFormat::Text => return Ok(false),
+        OutputFormat::Json | OutputFormat::Jsonl => {
+            json::write_match(out, rule, path, text, m, replacement)?
+        }
+        OutputFormat::Sarif => sarif::write_result(out, rule, path, text, m, replacement)?,
+    }
+    Ok(true)
+}
+
+/// A replacement made by [`Rewrite::replace`]
+struct Replaced {
+    /// The text of the replacement
+    text: String,
+    /// Where the replacement is in the new text
+    range: Range<usize>,
+    /// Where the match was in the original text
+    original: Range<usize>,
+    /// The end of the last replacement before this one, see [`Rewrite::undo`]
+    previous_until: usize,
+}
+
+/// Applies replacements for (sorted) matches to a text, one at a time. Shared
+/// by [`process_file`] and `mogglo test`, so that rule tests see the same
+/// replacements as `--replace`.
+struct Rewrite<'t> {
+    /// The Lua state used for matching in this text
+    lua: &'t LuaState,
+    /// The original text, which the matches refer to
+    original: &'t str,
+    /// The text with the replacements so far
+    text: String,
+    /// When doing multiple replacements, the earlier ones affect the offset of
+    /// the later ones.
+    offset: isize,
+    /// Replacements can't overlap, this is the end of the last one
+    replaced_until: usize,
+}
+
+impl<'t> Rewrite<'t> {
+    fn new(lua: &'t LuaState, original: &'t str) -> Self {
+        Self {
+            lua,
+            original,
+            text: original.to_string(),
+            offset: 0,
+            replaced_until: 0,
+        }
+    }
+
+    /// Replace a match, unless it overlaps an earlier replacement
+    fn replace(&mut self, replace: &Pattern, m: &Match) -> Option<Replaced> {
+        let original = m.root.byte_range();
+        if original.start < self.replaced_until {
+            return None;
+        }
+        let previous_until = self.replaced_until;
+        self.replaced_until = original.end;
+        // The match refers to the original text, not the one with earlier
+        // replacements applied.
+        let text = replace.replacement_with(self.lua, m, self.original);
+        let start =
+            usize::try_from(isize::try_from(original.start).unwrap() + self.offset).unwrap();
+        let end = start + original.len();
+        self.text.replace_range(start..end, &text);
+        self.offset +=
+            isize::try_from(text.len()).unwrap() - isize::try_from(original.len()).unwrap();
+        Some(Replaced {
+            range: start..start + text.len(),
+            text,
+            original,
+            previous_until,
+        })
+    }
+
+    /// Undo the last replacement, e.g., when it's declined with `--confirm`
+    fn undo(&mut self, replaced: Replaced) {
+        let original = &self.original[replaced.original.clone()];
+        self.text.replace_range(replaced.range, original);
+        self.offset -= isize::try_from(replaced.text.len()).unwrap()
+            - isize::try_from(replaced.original.len()).unwrap();
+        self.replaced_until = replaced.previous_until;
+    }
+}
+
+/// Returns whether to stop processing files. The new text of the file is
+/// passed to `write`, which writes it to the file.
+fn process_file(
+    args: &Args,
+    language: Language,
+    checks: &[Check],
+    f: &str,
+    out: &mut (impl Write + ?Sized),
+    err: &mut impl Write,
+    write: &mut dyn FnMut(String) -> Result<()>,
+) -> Result<bool> {
+    let (tree, text) = if f == "-" {
+        let text = stdin_string()?;
+        let tree = crate::pattern::parse(language, &text);
+        (tree, text)
+    } else {
+        let text = read_file(f)?;
+        let tree = crate::pattern::parse(language, &text);
+        (tree, text)
+    };
+    if handle_parse_errors(f, &tree, &args.on_parse_error, err)? {
+        return Ok(true);
+    }
+    // One Lua state for the whole file, see `LuaState::fresh`
+    let lua = LuaState::default();
+    let mut rewrite = Rewrite::new(&lua, &text);
+    let mut matches = Vec::new();
+    for check in checks {
+        for m in check.pat.matches_with(
+            &lua,
+            &tree,
+            &text,
+            &Env::default(),
+            args.recursive,
+            args.limit,
+        ) {
+            matches.push((check, m));
+        }
+    }
+    // Stable, so matches at the same position are in the order of the rules
+    matches.sort_by_key(|(_, m)| m.root.start_byte());
+    for (check, m) in matches {
+        let (title, label) = if args.rules.is_empty() {
+            ("Match", "Match")
+        } else {
+            (check.rule.id.as_str(), check.rule.message.as_str())
+        };
+        if let Some(p) = &check.replace {
+            let Some(replaced) = rewrite.replace(p, &m) else {
+                writeln!(
+                    err,
+                    "[WARN] Skipping replacement that overlaps an earlier one in {f}"
                 )?;
-                if args.confirm {
-                    eprint!("Replace (Y/n)? ");
-                    let mut buffer = String::new();
-                    io::stdin().read_line(&mut buffer)?;
-                    if !(buffer == "\n" || buffer == "Y\n" || buffer == "y\n") {
-                        let original = &text0[m.root.byte_range()];
-                        eprintln!("ORIGINAL {original}");
-                        text.replace_range(new_range, original);
-                        offset -= replacement_size - match_size;
+                continue;
+            };
+
+            let replacement = Some(replaced.text.as_str());
+            if args.diff || write_record(args, check.rule, out, f, &text, &m, replacement)? {
+                continue;
+            }
+            if args.only_matching {
+                // TODO: Don't print whole text here...?
+                writeln!(out, "{}", rewrite.text)?;
+                continue;
+            }
+            match_report(
+                out,
+                if args.dry_run {
+                    "Would replace"
+                } else {
+                    "Replacing"
+                },
+                f,
+                &text,
+                m.root.byte_range(),
+                &check.rule.pattern,
+                &m.env,
+                args.detail,
+                label,
+            )?;
+            match_report(
+                out,
+                "With",
+                f,
+                &rewrite.text,
+                replaced.range.clone(),
+                &check.rule.pattern,
+                &Env::default(),
+                args.detail,
+                "Replacement",
+            )?;
+            if args.confirm {
+                out.flush()?;
+                write!(err, "Replace (Y/n)? ")?;
+                err.flush()?;
+                let mut buffer = String::new();
+                io::stdin().read_line(&mut buffer)?;
+                if !(buffer == "\n" || buffer == "Y\n" || buffer == "y\n") {
+                    writeln!(err, "ORIGINAL {}", &text[m.root.byte_range()])?;
+                    rewrite.undo(replaced);
+                }
+            }
+        } else if write_record(args, check.rule, out, f, &text, &m, None)? {
+            continue;
+        } else if args.only_matching {
+            writeln!(out, "{}", m.root.utf8_text(text.as_bytes()).unwrap())?;
+        } else {
+            match_report(
+                out,
+                title,
+                f,
+                &text,
+                m.root.byte_range(),
+                &check.rule.pattern,
+                &m.env,
+                args.detail,
+                label,
+            )?;
+        }
+    }
+    let new_text = rewrite.text;
+    if args.diff {
+        if new_text != text {
+            let path = relative_path(f)?;
+            let path = path.display();
+            let diff = TextDiff::from_lines(&text, &new_text);
+            let a = format!("a/{path}");
+            let b = format!("b/{path}");
+            write!(out, "{}", diff.unified_diff().header(&a, &b))?;
+        }
+    } else if !args.dry_run && f != "-" {
+        write(new_text)?;
+    }
+    Ok(false)
+}
+
+/// The components of an absolute path, without `.` and `..`
+fn normalize(path: &Path) -> Vec<Component<'_>> {
+    let mut components = Vec::new();
+    for c in path.components() {
+        match c {
+            Component::CurDir => (),
+            Component::ParentDir if matches!(components.last(), Some(Component::Normal(_))) => {
+                components.pop();
+            }
+            c => components.push(c),
+        }
+    }
+    components
+}
+
+/// A path relative to the current directory, for `--diff` headers
+fn relative_path(path: &str) -> Result<PathBuf> {
+    let cwd = std::env::current_dir()?;
+    let path = cwd.join(path);
+    let (path, cwd) = (normalize(&path), normalize(&cwd));
+    let common = path.iter().zip(&cwd).take_while(|(p, c)| p == c).count();
+    let mut relative = PathBuf::new();
+    for _ in common..cwd.len() {
+        relative.push("..");
+    }
+    relative.extend(&path[common..]);
+    Ok(relative)
+}
+
+/// Process files on a pool of `jobs` threads. Output is buffered per file and
+/// printed in the order the files were given, and files are written in that
+/// order too, so that no file after one that stops processing is written.
+fn process_files_parallel(
+    args: &Args,
+    searches: &[Search],
+    files: &[(usize, String)],
+    jobs: usize,
+    stdout: &mut dyn Write,
+) -> Result<bool> {
+    let next = AtomicUsize::new(0);
+    let stop = AtomicBool::new(false);
+    let (tx, rx) = mpsc::channel();
+    thread::scope(|s| {
+        for _ in 0..jobs {
+            let tx = tx.clone();
+            let (next, stop) = (&next, &stop);
+            s.spawn(move || {
+                let checks = all_checks(searches);
+                while !stop.load(Ordering::Relaxed) {
+                    let i = next.fetch_add(1, Ordering::Relaxed);
+                    let Some((search, f)) = files.get(i) else {
+                        break;
+                    };
+                    let mut out = Vec::new();
+                    let mut err = Vec::new();
+                    let mut text = None;
+                    let mut write = |t| {
+                        text = Some(t);
+                        Ok(())
+                    };
+                    let result = process_file(
+                        args,
+                        searches[*search].0.language,
+                        &checks[*search],
+                        f,
+                        &mut out,
+                        &mut err,
+                        &mut write,
+                    );
+                    if !matches!(result, Ok(false)) {
+                        stop.store(true, Ordering::Relaxed);
+                    }
+                    if tx.send((i, result, out, err, text)).is_err() {
+                        break;
                     }
                 }
-            } else if args.only_matching {
-                println!("{}", m.root.utf8_text(text.as_bytes()).unwrap());
-            } else {
-                match_report(
-                    "Match",
-                    f,
-                    &text,
-                    m.root.byte_range(),
-                    &args.pattern,
-                    &m.env,
-                    args.detail,
-                    "Match",
-                )?;
+            });
+        }
+        drop(tx);
+
+        let mut pending = BTreeMap::new();
+        let mut i = 0;
+        for (j, result, out, err, text) in rx {
+            pending.insert(j, (result, out, err, text));
+            while let Some((result, out, err, text)) = pending.remove(&i) {
+                stdout.write_all(&out)?;
+                io::stderr().write_all(&err)?;
+                match result {
+                    Ok(false) => {
+                        if let Some(text) = text {
+                            std::fs::write(&files[i].1, text)?;
+                        }
+                        i += 1;
+                    }
+                    Ok(true) => return Ok(true),
+                    Err(e) => return Err(e),
+                }
+            }
+        }
+        Ok(false)
+    })
+}
+
+/// Run the CLI for a single language
+pub fn main(lang: Lang) -> Result<()> {
+    run(&[lang])
+}
+
+/// Run the CLI for several languages, see [`lang_files`]
+pub fn run(langs: &[Lang]) -> Result<()> {
+    let mut args = Args::parse();
+    let bin = std::env::args().next().unwrap_or_default();
+    let bin = Path::new(&bin).file_name().unwrap_or_default();
+    let paths = match &args.command {
+        Some(Command::Test { rules }) => rules,
+        None => &args.rules,
+    };
+    let loaded = load_rules(paths, langs)?;
+    let langs = select_langs(&args, langs, bin);
+    if let Some(Command::Test { .. }) = &args.command {
+        if !test::main(&langs, &loaded)? {
+            process::exit(1);
+        }
+        return Ok(());
+    }
+
+    if !args.rules.is_empty() || args.dump_ast {
+        // There's no pattern with `--rules` or `--dump-ast`, so the first
+        // positional argument is a file.
+        if let Some(f) = args.pattern.take() {
+            args.files.insert(0, f);
+        }
+    }
+    if args.files.is_empty() && !args.explain {
+        usage_error(
+            bin,
+            ErrorKind::MissingRequiredArgument,
+            "at least one FILE is required",
+        );
+    }
+    if let Some(ctx) = &args.context {
+        if !ctx.contains(ParseContext::PLACEHOLDER) {
+            usage_error(
+                bin,
+                ErrorKind::InvalidValue,
+                format!("--context must contain {}", ParseContext::PLACEHOLDER),
+            );
+        }
+    }
+    let from_files = !args.rules.is_empty();
+    if args.explain {
+        let [lang] = langs[..] else {
+            usage_error(
+                bin,
+                ErrorKind::MissingRequiredArgument,
+                "--explain requires --lang",
+            );
+        };
+        let node_types = NodeTypes::new(lang.node_types)
+            .expect("Internal error: failed to parse node_types.json");
+        let rules = rules(&args, lang, &loaded);
+        let language = lang.language;
+        check_patterns(
+            &rules,
+            language,
+            &node_types,
+            from_files,
+            false,
+            &mut io::stderr(),
+        )?;
+        return explain(&rules, language, &node_types, from_files, &mut io::stdout());
+    }
+    let files = lang_files(&args, &langs, bin)?;
+    if args.dump_ast {
+        return dump_ast(&files, &mut io::stdout());
+    }
+    if args.trace.is_some() && files.len() != 1 {
+        usage_error(
+            bin,
+            ErrorKind::WrongNumberOfValues,
+            "--trace requires exactly one FILE",
+        );
+    }
+
+    // Check the patterns in every language before searching any files
+    let several_languages = langs.len() > 1;
+    let mut searches: Vec<Search> = Vec::new();
+    let mut failed = false;
+    for lang in langs
+        .iter()
+        .filter(|l| files.iter().any(|(fl, _)| fl.name == l.name))
+    {
+        let node_types = NodeTypes::new(lang.node_types)
+            .expect("Internal error: failed to parse node_types.json");
+        let rules = rules(&args, lang, &loaded);
+        failed |= check_patterns(
+            &rules,
+            lang.language,
+            &node_types,
+            from_files,
+            several_languages,
+            &mut io::stderr(),
+        )?;
+        searches.push((lang, node_types, rules));
+    }
+    // Each file with the index of its search
+    let files: Vec<_> = files
+        .into_iter()
+        .map(|(lang, f)| {
+            let i = searches.iter().position(|(l, _, _)| l.name == lang.name);
+            (i.unwrap(), f)
+        })
+        .collect();
+    if failed && !args.allow_pattern_errors {
+        eprintln!("Pass --allow-pattern-errors to search anyway");
+        process::exit(1);
+    }
+    if let Some(pos) = &args.trace {
+        let (i, f) = &files[0];
+        let (lang, node_types, rules) = &searches[*i];
+        let checks = checks(rules, lang.language, node_types);
+        return trace::main(&mut io::stdout(), lang.language, &checks, f, pos);
+    }
+
+    // Prompts can't be interleaved, so confirmation is always sequential
+    let jobs = if args.confirm { 1 } else { args.jobs };
+    // JSON arrays and SARIF logs are only printed once all the matches are known
+    let mut json_lines = Vec::new();
+    let mut stdout = io::stdout();
+    let out: &mut dyn Write = if matches!(args.format, OutputFormat::Json | OutputFormat::Sarif) {
+        &mut json_lines
+    } else {
+        &mut stdout
+    };
+    let stop = if jobs <= 1 || files.len() <= 1 {
+        let checks = all_checks(&searches);
+        let mut stop = false;
+        for (i, f) in &files {
+            let mut write = |text| Ok(std::fs::write(f, text)?);
+            stop = process_file(
+                &args,
+                searches[*i].0.language,
+                &checks[*i],
+                f,
+                out,
+                &mut io::stderr(),
+                &mut write,
+            )?;
+            if stop {
+                break;
             }
         }
-        if !args.dry_run && f != "-" {
-            std::fs::write(f, text)?;
+        stop
+    } else {
+        process_files_parallel(&args, &searches, &files, jobs, out)?
+    };
+    match args.format {
+        OutputFormat::Json => json::lines_to_array(&json_lines, &mut io::stdout())?,
+        OutputFormat::Sarif => {
+            let rules: Vec<_> = searches
+                .into_iter()
+                .flat_map(|(_, _, rules)| rules)
+                .collect();
+            sarif::write_log(&json_lines, &rules, &mut io::stdout())?
         }
+        OutputFormat::Text | OutputFormat::Jsonl => (),
+    }
+    if stop {
+        process::exit(1);
     }
     Ok(())
 }
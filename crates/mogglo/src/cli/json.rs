@@ -0,0 +1,121 @@
+use std::{collections::BTreeMap, io::Write, ops::Range};
+
+use anyhow::Result;
+use tree_sitter::{Node, Point};
+
+use crate::{pattern::Match, rule::Rule};
+
+/// A position in a file. Lines and columns start at 1, columns count bytes.
+#[derive(Debug, serde::Serialize)]
+pub(super) struct Position {
+    pub(super) line: usize,
+    pub(super) column: usize,
+}
+
+impl From<Point> for Position {
+    fn from(p: Point) -> Self {
+        Self {
+            line: p.row + 1,
+            column: p.column + 1,
+        }
+    }
+}
+
+#[derive(Debug, serde::Serialize)]
+pub(super) struct Span<'a> {
+    pub(super) range: Range<usize>,
+    pub(super) start: Position,
+    pub(super) end: Position,
+    pub(super) text: &'a str,
+}
+
+impl<'a> Span<'a> {
+    pub(super) fn new(node: Node, text: &'a str) -> Self {
+        Self {
+            range: node.byte_range(),
+            start: Position::from(node.start_position()),
+            end: Position::from(node.end_position()),
+            text: node.utf8_text(text.as_bytes()).unwrap(),
+        }
+    }
+
+    /// The span from the start of one node to the end of another
+    fn between(first: Node, last: Node, text: &'a str) -> Self {
+        Self {
+            range: first.start_byte()..last.end_byte(),
+            start: Position::from(first.start_position()),
+            end: Position::from(last.end_position()),
+            text: &text[first.start_byte()..last.end_byte()],
+        }
+    }
+}
+
+#[derive(Debug, serde::Serialize)]
+struct MatchRecord<'a> {
+    rule: &'a str,
+    file: &'a str,
+    #[serde(flatten)]
+    span: Span<'a>,
+    bindings: BTreeMap<&'a str, Vec<Span<'a>>>,
+    /// Named ellipses, `None` if they matched no nodes
+    ellipses: BTreeMap<&'a str, Option<Span<'a>>>,
+    replacement: Option<&'a str>,
+}
+
+/// Write a match as a single line of JSON
+pub(super) fn write_match(
+    out: &mut (impl Write + ?Sized),
+    rule: &Rule,
+    path: &str,
+    text: &str,
+    m: &Match,
+    replacement: Option<&str>,
+) -> Result<()> {
+    let mut bindings = BTreeMap::new();
+    for (mvar, nodes) in &m.env.0 {
+        let mut nodes: Vec<_> = nodes.iter().collect();
+        nodes.sort_unstable_by_key(|n| n.byte_range().start);
+        bindings.insert(
+            mvar.0.as_str(),
+            nodes.into_iter().map(|n| Span::new(*n, text)).collect(),
+        );
+    }
+    let mut ellipses = BTreeMap::new();
+    for (mvar, nodes) in &m.env.1 {
+        let span = nodes
+            .first()
+            .zip(nodes.last())
+            .map(|(first, last)| Span::between(*first, *last, text));
+        ellipses.insert(mvar.0.as_str(), span);
+    }
+    let record = MatchRecord {
+        rule: &rule.id,
+        file: path,
+        span: Span::new(m.root, text),
+        bindings,
+        ellipses,
+        replacement,
+    };
+    serde_json::to_writer(&mut *out, &record)?;
+    writeln!(out)?;
+    Ok(())
+}
+
+/// Turn JSON lines written by [`write_match`] into a single JSON array
+pub(super) fn lines_to_array(lines: &[u8], out: &mut impl Write) -> Result<()> {
+    writeln!(out, "[")?;
+    let mut lines = lines
+        .split(|b| *b == b'\n')
+        .filter(|l| !l.is_empty())
+        .peekable();
+    while let Some(line) = lines.next() {
+        out.write_all(b"  ")?;
+        out.write_all(line)?;
+        if lines.peek().is_some() {
+            write!(out, ",")?;
+        }
+        writeln!(out)?;
+    }
+    writeln!(out, "]")?;
+    Ok(())
+}
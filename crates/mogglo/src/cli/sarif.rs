@@ -0,0 +1,156 @@
+use std::{collections::HashSet, fmt::Write as _, io::Write, path::Path};
+
+use anyhow::Result;
+use serde_json::{json, Value};
+use tree_sitter::Node;
+
+use crate::{
+    pattern::Match,
+    rule::{Rule, Severity},
+};
+
+const SCHEMA: &str = "https://json.schemastore.org/sarif-2.1.0.json";
+
+/// The directory that relative paths are relative to, see [`location`]
+const SRCROOT: &str = "%SRCROOT%";
+
+fn level(severity: Severity) -> &'static str {
+    match severity {
+        Severity::Error => "error",
+        Severity::Warning => "warning",
+        Severity::Info => "note",
+    }
+}
+
+/// SARIF region for a node. Columns count Unicode code points, see `columnKind`
+/// in [`write_log`].
+fn region(node: Node, text: &str) -> Value {
+    let column = |byte: usize, row_start: usize| text[row_start..byte].chars().count() + 1;
+    let start = node.start_position();
+    let end = node.end_position();
+    let range = node.byte_range();
+    json!({
+        "startLine": start.row + 1,
+        "startColumn": column(range.start, range.start - start.column),
+        "endLine": end.row + 1,
+        "endColumn": column(range.end, range.end - end.column),
+        "byteOffset": range.start,
+        "byteLength": range.len(),
+        "snippet": { "text": node.utf8_text(text.as_bytes()).unwrap() },
+    })
+}
+
+/// Percent-encode a path for use in a URI, keeping the slashes
+fn encode(path: &str) -> String {
+    let mut uri = String::new();
+    for b in path.bytes() {
+        match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' | b'/' => {
+                uri.push(char::from(b))
+            }
+            _ => write!(uri, "%{b:02X}").unwrap(),
+        }
+    }
+    uri
+}
+
+/// SARIF artifact location for a file: A `file://` URI for absolute paths,
+/// and otherwise a URI relative to [`SRCROOT`], the current directory
+fn location(path: &str) -> Value {
+    if Path::new(path).is_absolute() {
+        json!({ "uri": format!("file://{}", encode(path)) })
+    } else {
+        let path = path.strip_prefix("./").unwrap_or(path);
+        json!({ "uri": encode(path), "uriBaseId": SRCROOT })
+    }
+}
+
+/// Write a SARIF `result` as a single line of JSON
+pub(super) fn write_result(
+    out: &mut (impl Write + ?Sized),
+    rule: &Rule,
+    path: &str,
+    text: &str,
+    m: &Match,
+    replacement: Option<&str>,
+) -> Result<()> {
+    let location = location(path);
+    let mut result = json!({
+        "ruleId": rule.id,
+        "level": level(rule.severity),
+        "message": { "text": rule.message },
+        "locations": [{
+            "physicalLocation": {
+                "artifactLocation": location,
+                "region": region(m.root, text),
+            },
+        }],
+    });
+    if let Some(replacement) = replacement {
+        result["fixes"] = json!([{
+            "description": { "text": format!("Replace with `{replacement}`") },
+            "artifactChanges": [{
+                "artifactLocation": location,
+                "replacements": [{
+                    "deletedRegion": region(m.root, text),
+                    "insertedContent": { "text": replacement },
+                }],
+            }],
+        }]);
+    }
+    serde_json::to_writer(&mut *out, &result)?;
+    writeln!(out)?;
+    Ok(())
+}
+
+/// Where [`SRCROOT`] is, if the current directory is known
+fn original_uri_base_ids() -> Value {
+    let Ok(dir) = std::env::current_dir() else {
+        return json!({});
+    };
+    let dir = dir.to_string_lossy();
+    let dir = dir.strip_suffix('/').unwrap_or(&dir);
+    json!({ SRCROOT: { "uri": format!("file://{}/", encode(dir)) } })
+}
+
+/// Write a SARIF log with one run containing the results written by
+/// [`write_result`]
+pub(super) fn write_log(results: &[u8], rules: &[Rule], out: &mut impl Write) -> Result<()> {
+    let results = serde_json::Deserializer::from_slice(results)
+        .into_iter::<Value>()
+        .collect::<Result<Vec<_>, _>>()?;
+    // Rules for different languages can share an id, but not a description,
+    // see `load_rules`
+    let mut ids = HashSet::new();
+    let rules: Vec<_> = rules
+        .iter()
+        .filter(|r| ids.insert(&r.id))
+        .map(|r| {
+            json!({
+                "id": r.id,
+                "shortDescription": { "text": r.message },
+                "defaultConfiguration": { "level": level(r.severity) },
+            })
+        })
+        .collect();
+    let log = json!({
+        "$schema": SCHEMA,
+        "version": "2.1.0",
+        "runs": [{
+            "tool": {
+                "driver": {
+                    "name": "mogglo",
+                    "informationUri": env!("CARGO_PKG_HOMEPAGE"),
+                    "version": env!("CARGO_PKG_VERSION"),
+                    "rules": rules,
+                },
+            },
+            "columnKind": "unicodeCodePoints",
+            "originalUriBaseIds": original_uri_base_ids(),
+            "results": results,
+        }],
+    });
+    serde_json::to_writer_pretty(&mut *out, &log)?;
+    writeln!(out)?;
+    Ok(())
+}
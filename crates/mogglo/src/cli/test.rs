@@ -0,0 +1,108 @@
+use std::io::{self, Write};
+
+use anyhow::Result;
+use similar::TextDiff;
+use tree_sitter::Language;
+
+use crate::{
+    env::Env,
+    lua::LuaState,
+    node_types::NodeTypes,
+    pattern::{parse, Match, Pattern},
+    rule::Rule,
+};
+
+use super::{checks, Check, Lang, Rewrite};
+
+/// Apply a replacement to each of the (sorted) matches, like `--replace`
+fn rewrite(lua: &LuaState, replace: &Pattern, code: &str, matches: &[Match]) -> String {
+    let mut rewrite = Rewrite::new(lua, code);
+    for m in matches {
+        rewrite.replace(replace, m);
+    }
+    rewrite.text
+}
+
+/// Run a rule on its examples, returning a description of each failure
+fn test_rule(check: &Check, language: Language) -> Vec<String> {
+    let mut failures = Vec::new();
+    for (i, code) in check.rule.valid.iter().enumerate() {
+        let tree = parse(language, code);
+        let matches = check
+            .pat
+            .matches(&tree, code, &Env::default(), false, Some(1));
+        if !matches.is_empty() {
+            failures.push(format!("valid example {} matched:\n{code}", i + 1));
+        }
+    }
+    for (i, example) in check.rule.invalid.iter().enumerate() {
+        let code = &example.code;
+        let tree = parse(language, code);
+        let lua = LuaState::default();
+        let mut matches = check
+            .pat
+            .matches_with(&lua, &tree, code, &Env::default(), false, None);
+        if matches.is_empty() {
+            failures.push(format!("invalid example {} didn't match:\n{code}", i + 1));
+            continue;
+        }
+        let Some(expected) = &example.fixed else {
+            continue;
+        };
+        let Some(replace) = &check.replace else {
+            failures.push(format!(
+                "invalid example {} has a fix, but the rule has no replacement",
+                i + 1
+            ));
+            continue;
+        };
+        matches.sort_unstable_by_key(|m| m.root.start_byte());
+        let actual = rewrite(&lua, replace, code, &matches);
+        let (expected, actual) = (expected.trim_end(), actual.trim_end());
+        if expected != actual {
+            let diff = TextDiff::from_lines(expected, actual)
+                .unified_diff()
+                .missing_newline_hint(false)
+                .header("expected", "actual")
+                .to_string();
+            failures.push(format!("wrong fix for invalid example {}:\n{diff}", i + 1));
+        }
+    }
+    failures
+}
+
+/// Test the rules for the given languages against their examples, returns
+/// whether all of them passed
+pub(super) fn main(langs: &[&Lang], rules: &[Rule]) -> Result<bool> {
+    let mut stdout = io::stdout();
+    let (mut passed, mut failed) = (0, 0);
+    for lang in langs {
+        let rules: Vec<_> = rules
+            .iter()
+            .filter(|r| r.language == lang.name)
+            .cloned()
+            .collect();
+        if rules.is_empty() {
+            continue;
+        }
+        let node_types = NodeTypes::new(lang.node_types)
+            .expect("Internal error: failed to parse node_types.json");
+        for check in checks(&rules, lang.language, &node_types) {
+            let failures = test_rule(&check, lang.language);
+            if failures.is_empty() {
+                passed += 1;
+                writeln!(stdout, "[PASS] {}", check.rule.id)?;
+                continue;
+            }
+            failed += 1;
+            writeln!(stdout, "[FAIL] {}", check.rule.id)?;
+            for failure in failures {
+                for line in failure.lines() {
+                    writeln!(stdout, "  {line}")?;
+                }
+            }
+        }
+    }
+    writeln!(stdout, "{passed} passed, {failed} failed")?;
+    Ok(failed == 0)
+}
@@ -0,0 +1,165 @@
+use std::{io::Write, ops::Range, str::FromStr};
+
+use anyhow::Result;
+use ariadne::{Color, Label, Report, ReportKind, Source};
+use tree_sitter::{Language, Node, Point};
+
+use crate::{
+    pattern::parse,
+    trace::{Event, Step, Trace},
+};
+
+use super::{read_file, stdin_string, Check};
+
+/// Where to trace matching, see `--trace`. Lines and columns start at 1,
+/// columns count bytes.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Position {
+    /// `LINE`, from its first non-blank character
+    Line(usize),
+    /// `LINE:COLUMN`
+    Point(Point),
+    /// `START..END`, a byte range as printed by `--dump-ast`
+    Bytes(Range<usize>),
+}
+
+impl FromStr for Position {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let number = |n: &str| {
+            n.trim()
+                .parse::<usize>()
+                .map_err(|_| format!("expected LINE:COLUMN or START..END, found {s}"))
+        };
+        if let Some((start, end)) = s.split_once("..") {
+            return Ok(Position::Bytes(number(start)?..number(end)?));
+        }
+        let start_at_1 = || "lines and columns start at 1".to_string();
+        let Some((line, column)) = s.split_once(':') else {
+            let line = number(s)?.checked_sub(1).ok_or_else(start_at_1)?;
+            return Ok(Position::Line(line));
+        };
+        let line = number(line)?.checked_sub(1).ok_or_else(start_at_1)?;
+        let column = number(column)?.checked_sub(1).ok_or_else(start_at_1)?;
+        Ok(Position::Point(Point::new(line, column)))
+    }
+}
+
+/// The nodes to trace: The smallest named node at the position, and its
+/// ancestors that start at the same place
+fn candidates<'tree>(root: Node<'tree>, text: &str, pos: &Position) -> Vec<Node<'tree>> {
+    let node = match pos {
+        Position::Line(l) => {
+            let line = text.lines().nth(*l).unwrap_or_default();
+            let p = Point::new(*l, line.len() - line.trim_start().len());
+            root.named_descendant_for_point_range(p, p)
+        }
+        Position::Point(p) => root.named_descendant_for_point_range(*p, *p),
+        Position::Bytes(r) => root.named_descendant_for_byte_range(r.start, r.end),
+    };
+    let mut nodes = Vec::new();
+    let mut node = node.unwrap_or(root);
+    let start = node.start_byte();
+    nodes.push(node);
+    while let Some(parent) = node.parent().filter(|p| p.start_byte() == start) {
+        nodes.push(parent);
+        node = parent;
+    }
+    nodes
+}
+
+/// Code on one line, shortened
+fn abbreviate(code: &str) -> String {
+    const MAX: usize = 40;
+    let code = code.split_whitespace().collect::<Vec<_>>().join(" ");
+    if code.chars().count() <= MAX {
+        return code;
+    }
+    code.chars().take(MAX - 3).collect::<String>() + "..."
+}
+
+/// Write one line per comparison, with the failure that it led to right after
+/// it (if any)
+fn write_steps(out: &mut impl Write, steps: &[Step], text: &str) -> Result<()> {
+    let mut steps = steps.iter().peekable();
+    while let Some(step) = steps.next() {
+        let indent = "  ".repeat(step.depth);
+        let Range { start, end } = step.candidate;
+        let candidate = abbreviate(&text[step.candidate.clone()]);
+        let goal = abbreviate(&step.goal);
+        write!(out, "{indent}`{goal}` ~ `{candidate}` {start}..{end}")?;
+        let failure = if step.event.is_failure() {
+            Some(step)
+        } else {
+            steps.next_if(|s| {
+                s.event.is_failure()
+                    && s.depth == step.depth
+                    && s.goal == step.goal
+                    && s.candidate == step.candidate
+            })
+        };
+        if let Some(f) = failure {
+            write!(out, ": {}", f.event)?;
+        }
+        writeln!(out)?;
+    }
+    Ok(())
+}
+
+fn failure_report(out: &mut impl Write, path: &str, text: &str, step: &Step) -> Result<()> {
+    let mut builder = Report::build(ReportKind::Error, path, step.candidate.start)
+        .with_message(step.event.to_string())
+        .with_label(
+            Label::new((path, step.candidate.clone()))
+                .with_message(format!("Compared to `{}`", abbreviate(&step.goal)))
+                .with_color(Color::Red),
+        );
+    if let Event::Metavar { name, bound } = &step.event {
+        builder = builder.with_label(
+            Label::new((path, bound.clone()))
+                .with_message(format!("${name} was bound here"))
+                .with_color(Color::Cyan),
+        );
+    }
+    builder.finish().write((path, Source::from(text)), out)?;
+    Ok(())
+}
+
+/// Match each rule at a position in a file, printing the decisions made while
+/// matching and the deepest failure (see [`Trace::deepest_failure`])
+pub(super) fn main(
+    out: &mut impl Write,
+    language: Language,
+    checks: &[Check],
+    path: &str,
+    pos: &Position,
+) -> Result<()> {
+    let text = if path == "-" {
+        stdin_string()?
+    } else {
+        read_file(path)?
+    };
+    let tree = parse(language, &text);
+    let nodes = candidates(tree.root_node(), &text, pos);
+    for check in checks {
+        if checks.len() > 1 {
+            writeln!(out, "# {}", check.rule.id)?;
+        }
+        let trace = Trace::new();
+        let matched = nodes
+            .iter()
+            .find_map(|node| check.pat.trace(*node, &text, &trace));
+        write_steps(out, &trace.steps(), &text)?;
+        if let Some(m) = matched {
+            let Range { start, end } = m.root.byte_range();
+            writeln!(out, "Matched {} at {start}..{end}", m.root.kind())?;
+            continue;
+        }
+        match trace.deepest_failure() {
+            Some(step) => failure_report(out, path, &text, &step)?,
+            None => writeln!(out, "No match")?,
+        }
+    }
+    Ok(())
+}
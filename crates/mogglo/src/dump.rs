@@ -0,0 +1,54 @@
+use std::fmt::Write;
+
+use tree_sitter::{Node, TreeCursor};
+
+/// A syntax tree, one node per line, with the field, kind, and byte range of
+/// each node and the text of named leaves. Anonymous nodes are quoted.
+pub fn dump(node: Node, text: &str) -> String {
+    dump_with(node, text, &|_| None)
+}
+
+/// Like [`dump`], but with a comment on some nodes
+pub(crate) fn dump_with(node: Node, text: &str, note: &dyn Fn(Node) -> Option<String>) -> String {
+    let mut out = String::new();
+    write_node(&mut node.walk(), text, 0, note, &mut out);
+    out
+}
+
+fn write_node(
+    cursor: &mut TreeCursor,
+    text: &str,
+    depth: usize,
+    note: &dyn Fn(Node) -> Option<String>,
+    out: &mut String,
+) {
+    let node = cursor.node();
+    out.push_str(&"  ".repeat(depth));
+    if let Some(field) = cursor.field_name() {
+        let _ = write!(out, "{field}: ");
+    }
+    if node.is_named() {
+        out.push_str(node.kind());
+    } else {
+        let _ = write!(out, "{:?}", node.kind());
+    }
+    let _ = write!(out, " {}..{}", node.start_byte(), node.end_byte());
+    if node.is_missing() {
+        out.push_str(" (missing)");
+    } else if node.is_named() && node.child_count() == 0 {
+        let _ = write!(out, " {:?}", &text[node.byte_range()]);
+    }
+    if let Some(n) = note(node) {
+        let _ = write!(out, "  # {n}");
+    }
+    out.push('\n');
+    if cursor.goto_first_child() {
+        loop {
+            write_node(cursor, text, depth + 1, note, out);
+            if !cursor.goto_next_sibling() {
+                break;
+            }
+        }
+        cursor.goto_parent();
+    }
+}
@@ -1,21 +1,45 @@
-use std::collections::{HashMap, HashSet};
+use std::{
+    collections::{HashMap, HashSet},
+    ops::Range,
+};
 
 use tree_sitter::Node;
 
 #[derive(Clone, Debug, Eq, Hash, PartialEq)]
 pub struct Metavar(pub(crate) String);
 
+/// Bindings of metavariables to nodes, and of named ellipses (`$..x`) to runs
+/// of sibling nodes
 #[derive(Clone, Debug, Default, Eq, PartialEq)]
-pub struct Env<'tree>(pub(crate) HashMap<Metavar, HashSet<Node<'tree>>>);
+pub struct Env<'tree>(
+    pub(crate) HashMap<Metavar, HashSet<Node<'tree>>>,
+    pub(crate) HashMap<Metavar, Vec<Node<'tree>>>,
+);
 
 impl<'tree> Env<'tree> {
+    /// Add the bindings from another environment. Named ellipses that are
+    /// already bound keep their siblings.
     pub fn extend(&mut self, env: Env<'tree>) {
         for (mvar, bindings) in env.0 {
             self.0.entry(mvar).or_default().extend(bindings);
         }
+        for (mvar, nodes) in env.1 {
+            self.1.entry(mvar).or_insert(nodes);
+        }
     }
 
     pub fn insert(&mut self, mvar: Metavar, node: Node<'tree>) {
         self.0.entry(mvar).or_default().insert(node);
     }
+
+    pub fn insert_siblings(&mut self, mvar: Metavar, nodes: Vec<Node<'tree>>) {
+        self.1.insert(mvar, nodes);
+    }
+
+    /// The byte range spanned by the siblings bound to a named ellipsis, or
+    /// `None` if it matched no nodes
+    pub fn siblings_range(&self, mvar: &Metavar) -> Option<Range<usize>> {
+        let nodes = self.1.get(mvar)?;
+        Some(nodes.first()?.start_byte()..nodes.last()?.end_byte())
+    }
 }
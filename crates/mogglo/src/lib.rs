@@ -1,6 +1,9 @@
 #[cfg(feature = "cli")]
 pub mod cli;
+pub mod dump;
 pub mod env;
 pub mod lua;
 pub mod node_types;
 pub mod pattern;
+pub mod rule;
+pub mod trace;
@@ -1,15 +1,80 @@
+use std::{cell::OnceCell, ops::Range, sync::OnceLock};
+
 use regex::Regex;
-use rlua::{prelude::LuaError, Lua};
+use rlua::{
+    prelude::{LuaContext, LuaError},
+    Lua, RegistryKey, Table,
+};
 use tree_sitter::Node;
 
 use crate::{
     env::{Env, Metavar},
     node_types::NodeTypes,
+    pattern::PatternError,
 };
 
 pub(crate) mod node;
 pub(crate) mod pattern;
 
+use node::LuaNode;
+
+/// Name of the registry value holding the globals of Lua snippets
+const ENV: &str = "mogglo_env";
+
+/// A Lua state that is only created once some Lua code is evaluated.
+///
+/// Snippets don't see the real global table, but a table of globals that falls
+/// back to it. [`LuaState::fresh`] swaps in a new such table, so the same state
+/// can be reused for many candidate nodes without leaking globals between them.
+#[derive(Default)]
+pub(crate) struct LuaState(OnceCell<Lua>);
+
+impl LuaState {
+    pub(crate) fn get(&self) -> &Lua {
+        self.0.get_or_init(|| {
+            let lua = Lua::new();
+            lua.context(|lua_ctx| lua_ctx.set_named_registry_value(ENV, new_env(lua_ctx)?))
+                .expect("Failed to initialize Lua");
+            lua
+        })
+    }
+
+    /// Run a function with fresh globals, restoring the current ones afterwards
+    pub(crate) fn fresh<T>(&self, f: impl FnOnce() -> T) -> T {
+        let Some(lua) = self.0.get() else {
+            return f();
+        };
+        let saved = lua
+            .context(|lua_ctx| -> Result<RegistryKey, LuaError> {
+                let saved = lua_ctx.create_registry_value(env(lua_ctx)?)?;
+                lua_ctx.set_named_registry_value(ENV, new_env(lua_ctx)?)?;
+                Ok(saved)
+            })
+            .expect("Failed to reset Lua globals");
+        let result = f();
+        lua.context(|lua_ctx| {
+            let env: Table = lua_ctx.registry_value(&saved)?;
+            lua_ctx.set_named_registry_value(ENV, env)?;
+            lua_ctx.remove_registry_value(saved)
+        })
+        .expect("Failed to restore Lua globals");
+        result
+    }
+}
+
+fn new_env(lua_ctx: LuaContext) -> Result<Table, LuaError> {
+    let env = lua_ctx.create_table()?;
+    let meta = lua_ctx.create_table()?;
+    meta.set("__index", lua_ctx.globals())?;
+    env.set_metatable(Some(meta));
+    Ok(env)
+}
+
+/// The table of globals for Lua snippets, see [`LuaState`]
+pub(crate) fn env(lua_ctx: LuaContext) -> Result<Table, LuaError> {
+    lua_ctx.named_registry_value(ENV)
+}
+
 #[derive(Debug, Eq, PartialEq)]
 pub(crate) struct LuaData<'a> {
     pub(crate) env: &'a Env<'a>,
@@ -21,6 +86,10 @@ impl<'a> LuaData<'a> {
     fn node_text(&self, node: &Node) -> &str {
         node.utf8_text(self.text.as_bytes()).unwrap()
     }
+
+    fn siblings_text(&self, mvar: &Metavar) -> &str {
+        self.env.siblings_range(mvar).map_or("", |r| &self.text[r])
+    }
 }
 
 pub(crate) fn eval_lua_scope<
@@ -36,12 +105,18 @@ pub(crate) fn eval_lua_scope<
 where
     'lua: 'scope,
 {
-    let globals = lua_ctx.globals();
+    let globals = env(lua_ctx)?;
+    let nodes = lua_ctx.create_table()?;
     for (mvar, val) in &data.env.0 {
         if let Some(v) = val.iter().next() {
             globals.set(mvar.0.clone(), data.node_text(v))?;
+            nodes.set(mvar.0.clone(), LuaNode::new(*v, data.text))?;
         }
     }
+    globals.set("nodes", nodes)?;
+    for mvar in data.env.1.keys() {
+        globals.set(mvar.0.clone(), data.siblings_text(mvar))?;
+    }
 
     globals.set(
         "is_child_of",
@@ -71,10 +146,46 @@ where
         })?,
     )?;
 
+    globals.set(
+        "fields_of",
+        scope.create_function(|lua_ctx, k: String| {
+            let fields = lua_ctx.create_table()?;
+            for (name, field) in data.node_types.fields_of(&k) {
+                let f = lua_ctx.create_table()?;
+                f.set("multiple", field.multiple)?;
+                f.set("required", field.required)?;
+                f.set(
+                    "types",
+                    lua_ctx.create_sequence_from(field.types.iter().map(|t| t.ty.as_ref()))?,
+                )?;
+                fields.set(name, f)?;
+            }
+            Ok(fields)
+        })?,
+    )?;
+
+    globals.set(
+        "allowed_in_field",
+        scope.create_function(|lua_ctx, (k, f): (String, String)| {
+            lua_ctx.create_sequence_from(data.node_types.allowed_in_field(&k, &f))
+        })?,
+    )?;
+
+    globals.set(
+        "can_contain",
+        scope.create_function(|_, (p, c): (String, String)| {
+            Ok(data.node_types.can_contain(&p, &c))
+        })?,
+    )?;
+
     globals.set(
         "meta",
         scope.create_function(|_, k: String| {
-            Ok(data.env.0.get(&Metavar(k)).and_then(|s| {
+            let mvar = Metavar(k);
+            if data.env.1.contains_key(&mvar) {
+                return Ok(Some(data.siblings_text(&mvar)));
+            }
+            Ok(data.env.0.get(&mvar).and_then(|s| {
                 let v: Vec<_> = s.iter().collect();
                 if v.len() == 1 {
                     Some(v[0].utf8_text(data.text.as_bytes()).unwrap())
@@ -85,6 +196,18 @@ where
         })?,
     )?;
 
+    globals.set(
+        "node",
+        scope.create_function(|_, k: String| {
+            Ok(data
+                .env
+                .0
+                .get(&Metavar(k))
+                .and_then(|s| s.iter().next())
+                .map(|n| LuaNode::new(*n, data.text)))
+        })?,
+    )?;
+
     globals.set(
         "rx",
         scope.create_function(|_, (r, s): (String, String)| match Regex::new(&r) {
@@ -106,16 +229,89 @@ pub(crate) fn eval_lua_ctx<T: Clone + Default + for<'l> rlua::FromLua<'l> + 'sta
 ) -> Result<T, LuaError>
 where
 {
-    let loaded = lua_ctx.load(code.as_bytes());
+    let loaded = lua_ctx
+        .load(code.as_bytes())
+        .set_environment(env(lua_ctx)?)?;
     lua_ctx.scope(|scope| eval_lua_scope(lua_ctx, scope, loaded, data))
 }
 
 pub(crate) fn eval_lua<T: Clone + Default + for<'l> rlua::FromLua<'l> + 'static>(
-    lua: &Lua,
+    lua: &LuaState,
     code: &str,
     data: &LuaData,
 ) -> Result<T, LuaError>
 where
 {
-    lua.context(|lua_ctx| eval_lua_ctx(lua_ctx, code, data))
+    lua.get()
+        .context(|lua_ctx| eval_lua_ctx(lua_ctx, code, data))
+}
+
+/// The arguments of a function call that are string literals, with their
+/// positions, given the code after the opening parenthesis
+fn literal_args(code: &str) -> Vec<Option<(&str, Range<usize>)>> {
+    let mut args = Vec::new();
+    let mut depth = 0;
+    let mut arg_start = 0;
+    let mut in_string = false;
+    for (i, c) in code.char_indices() {
+        match c {
+            '"' => in_string = !in_string,
+            _ if in_string => (),
+            '(' | '{' | '[' => depth += 1,
+            ')' | '}' | ']' if depth > 0 => depth -= 1,
+            ',' | ')' if depth == 0 => {
+                let arg = &code[arg_start..i];
+                // Skip the leading whitespace and quote
+                let start = arg_start + arg.len() - arg.trim_start().len() + 1;
+                let literal = arg
+                    .trim()
+                    .strip_prefix('"')
+                    .and_then(|a| a.strip_suffix('"'))
+                    .filter(|a| !a.contains(['"', '\\']));
+                args.push(literal.map(|l| (l, start..start + l.len())));
+                if c == ')' {
+                    break;
+                }
+                arg_start = i + 1;
+            }
+            _ => (),
+        }
+    }
+    args
+}
+
+/// Kinds of nodes that don't exist, in string literals passed to the functions
+/// that take kinds (see `doc/api.md`) or compared to `kind()`
+pub fn check_kinds(node_types: &NodeTypes, code: &str) -> Vec<PatternError> {
+    static CALL: OnceLock<Regex> = OnceLock::new();
+    static COMPARE: OnceLock<Regex> = OnceLock::new();
+    let call = CALL.get_or_init(|| {
+        Regex::new(
+            r"\b(is_child_of|is_descendant_of|is_parent_of|is_ancestor_of|can_contain|fields_of|allowed_in_field)\s*\(",
+        )
+        .unwrap()
+    });
+    let compare = COMPARE.get_or_init(|| Regex::new(r#"kind\(\)\s*[=~]=\s*"([^"\\]*)""#).unwrap());
+
+    let mut kinds = Vec::new();
+    for caps in call.captures_iter(code) {
+        let end = caps.get(0).unwrap().end();
+        // The second argument of `allowed_in_field` is a field, not a kind
+        let n = match &caps[1] {
+            "fields_of" | "allowed_in_field" => 1,
+            _ => 2,
+        };
+        for (kind, span) in literal_args(&code[end..]).into_iter().take(n).flatten() {
+            kinds.push((kind, span.start + end..span.end + end));
+        }
+    }
+    for caps in compare.captures_iter(code) {
+        let m = caps.get(1).unwrap();
+        kinds.push((m.as_str(), m.range()));
+    }
+    kinds
+        .into_iter()
+        .filter(|(kind, _)| !node_types.is_kind(kind))
+        .map(|(kind, span)| PatternError::unknown_kind(node_types, kind, span))
+        .collect()
 }
@@ -1,5 +1,5 @@
-use rlua::UserData;
-use tree_sitter::Node;
+use rlua::{Context, Table, UserData};
+use tree_sitter::{Node, Point};
 
 #[derive(Clone, Copy, Debug)]
 pub(crate) struct LuaNode {
@@ -30,14 +30,69 @@ impl LuaNode {
         self.node.child(idx).map(|n| self.with_node(n))
     }
 
+    fn child_by_field_name(&self, field: &str) -> Option<Self> {
+        self.node
+            .child_by_field_name(field)
+            .map(|n| self.with_node(n))
+    }
+
     fn child_count(&self) -> usize {
         self.node.child_count()
     }
 
+    /// Proper descendants, in pre-order
+    fn descendants(&self) -> Vec<Self> {
+        let mut descendants = Vec::new();
+        let mut cursor = self.node.walk();
+        if !cursor.goto_first_child() {
+            return descendants;
+        }
+        loop {
+            descendants.push(self.with_node(cursor.node()));
+            if cursor.goto_first_child() || cursor.goto_next_sibling() {
+                continue;
+            }
+            loop {
+                if !cursor.goto_parent() || cursor.node() == self.node {
+                    return descendants;
+                }
+                if cursor.goto_next_sibling() {
+                    break;
+                }
+            }
+        }
+    }
+
+    /// Name of the field of the parent that holds this node
+    fn field_name(&self) -> Option<&'static str> {
+        let parent = self.node.parent()?;
+        let mut cursor = parent.walk();
+        let mut found = cursor.goto_first_child();
+        while found {
+            if cursor.node() == self.node {
+                return cursor.field_name();
+            }
+            found = cursor.goto_next_sibling();
+        }
+        None
+    }
+
+    fn field_name_for_child(&self, idx: usize) -> Option<&'static str> {
+        self.child(idx).and_then(|c| c.field_name())
+    }
+
     fn kind(&self) -> &'static str {
         self.node.kind()
     }
 
+    fn named_child(&self, idx: usize) -> Option<Self> {
+        self.node.named_child(idx).map(|n| self.with_node(n))
+    }
+
+    fn named_child_count(&self) -> usize {
+        self.node.named_child_count()
+    }
+
     fn next_named_sibling(&self) -> Option<Self> {
         self.node.next_named_sibling().map(|n| self.with_node(n))
     }
@@ -63,11 +118,43 @@ impl LuaNode {
     }
 }
 
+fn point<'lua>(lua_ctx: Context<'lua>, p: Point) -> rlua::Result<Table<'lua>> {
+    let t = lua_ctx.create_table()?;
+    t.set("row", p.row)?;
+    t.set("column", p.column)?;
+    Ok(t)
+}
+
 impl UserData for LuaNode {
     fn add_methods<'lua, T: rlua::UserDataMethods<'lua, Self>>(methods: &mut T) {
         methods.add_method("child", |_, this, i: usize| Ok(this.child(i)));
+        methods.add_method("child_by_field_name", |_, this, f: String| {
+            Ok(this.child_by_field_name(&f))
+        });
         methods.add_method("child_count", |_, this, _: ()| Ok(this.child_count()));
+        methods.add_method("byte_range", |_, this, _: ()| {
+            Ok((this.node.start_byte(), this.node.end_byte()))
+        });
+        methods.add_method("descendants", |lua_ctx, this, _: ()| {
+            let mut descendants = this.descendants().into_iter();
+            lua_ctx.create_function_mut(move |_, _: rlua::MultiValue| Ok(descendants.next()))
+        });
+        methods.add_method("end_point", |lua_ctx, this, _: ()| {
+            point(lua_ctx, this.node.end_position())
+        });
+        methods.add_method("field_name", |_, this, _: ()| Ok(this.field_name()));
+        methods.add_method("field_name_for_child", |_, this, i: usize| {
+            Ok(this.field_name_for_child(i))
+        });
+        methods.add_method("has_error", |_, this, _: ()| Ok(this.node.has_error()));
+        methods.add_method("is_error", |_, this, _: ()| Ok(this.node.is_error()));
+        methods.add_method("is_missing", |_, this, _: ()| Ok(this.node.is_missing()));
+        methods.add_method("is_named", |_, this, _: ()| Ok(this.node.is_named()));
         methods.add_method("kind", |_, this, _: ()| Ok(this.kind()));
+        methods.add_method("named_child", |_, this, i: usize| Ok(this.named_child(i)));
+        methods.add_method("named_child_count", |_, this, _: ()| {
+            Ok(this.named_child_count())
+        });
         methods.add_method("next_named_sibling", |_, this, _: ()| {
             Ok(this.next_named_sibling())
         });
@@ -77,6 +164,10 @@ impl UserData for LuaNode {
             Ok(this.prev_named_sibling())
         });
         methods.add_method("prev_sibling", |_, this, _: ()| Ok(this.prev_sibling()));
+        methods.add_method("start_point", |lua_ctx, this, _: ()| {
+            point(lua_ctx, this.node.start_position())
+        });
         methods.add_method("text", |_, this, _: ()| Ok(this.text()));
+        methods.add_method("to_sexp", |_, this, _: ()| Ok(this.node.to_sexp()));
     }
 }
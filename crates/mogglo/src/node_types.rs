@@ -19,12 +19,13 @@ pub struct Node<'a> {
     subtypes: Vec<Subtype<'a>>,
 }
 
+/// Children of a node that aren't in any field
 #[derive(Clone, Debug, Default, Hash, Eq, PartialEq, serde::Deserialize, serde::Serialize)]
-struct Children<'a> {
-    multiple: bool,
-    required: bool,
+pub struct Children<'a> {
+    pub multiple: bool,
+    pub required: bool,
     #[serde(borrow)]
-    types: Vec<Subtype<'a>>,
+    pub types: Vec<Subtype<'a>>,
 }
 
 #[derive(Clone, Debug, Hash, Eq, PartialEq, serde::Deserialize, serde::Serialize)]
@@ -42,11 +43,14 @@ pub struct Subtype<'a> {
     pub named: bool,
 }
 
-#[allow(dead_code)]
 #[derive(Clone, Debug, Eq, PartialEq)]
 pub struct NodeTypes<'a> {
     parents: HashMap<Cow<'a, str>, HashSet<Cow<'a, str>>>,
     children: HashMap<Cow<'a, str>, HashSet<Cow<'a, str>>>,
+    /// The fields of each kind of node
+    fields: HashMap<Cow<'a, str>, HashMap<Cow<'a, str>, Field<'a>>>,
+    /// The children of each kind of node that aren't in any field
+    contents: HashMap<Cow<'a, str>, Children<'a>>,
 }
 
 impl<'a> NodeTypes<'a> {
@@ -54,7 +58,15 @@ impl<'a> NodeTypes<'a> {
         let nodes: Vec<Node> = serde_json::from_str(node_types_json_str)?;
         let mut parents = HashMap::with_capacity(nodes.len());
         let mut children = HashMap::with_capacity(nodes.len());
+        let mut fields = HashMap::with_capacity(nodes.len());
+        let mut contents = HashMap::with_capacity(nodes.len());
         for node in nodes {
+            if !node.fields.is_empty() {
+                fields.insert(node.ty.clone(), node.fields);
+            }
+            if !node.children.types.is_empty() {
+                contents.insert(node.ty.clone(), node.children);
+            }
             let mut subs = HashSet::with_capacity(node.subtypes.len());
             for sub in node.subtypes.into_iter() {
                 parents
@@ -65,7 +77,29 @@ impl<'a> NodeTypes<'a> {
             }
             children.insert(node.ty, subs);
         }
-        Ok(NodeTypes { parents, children })
+        Ok(NodeTypes {
+            parents,
+            children,
+            fields,
+            contents,
+        })
+    }
+
+    /// Whether there is a kind of node with this name
+    pub fn is_kind(&self, kind: &str) -> bool {
+        self.children.contains_key(kind)
+    }
+
+    /// The name of a kind of node that's close to the given one, for
+    /// suggestions when it doesn't exist
+    pub fn suggest(&self, kind: &str) -> Option<&str> {
+        let max = kind.len() / 3;
+        self.children
+            .keys()
+            .map(|k| (edit_distance(kind, k), k.as_ref()))
+            .filter(|(d, _)| *d <= max)
+            .min()
+            .map(|(_, k)| k)
     }
 
     pub(crate) fn is_child_of(&self, child: &str, parent: &str) -> bool {
@@ -93,6 +127,67 @@ impl<'a> NodeTypes<'a> {
                 cs.contains(desc) || cs.iter().any(|c| self.is_descendant_of(c, desc))
             })
     }
+
+    /// The fields of a kind of node, sorted by name
+    pub fn fields_of(&self, kind: &str) -> Vec<(&str, &Field<'a>)> {
+        let mut fields: Vec<_> = self
+            .fields
+            .get(kind)
+            .into_iter()
+            .flatten()
+            .map(|(name, field)| (name.as_ref(), field))
+            .collect();
+        fields.sort_by_key(|(name, _)| *name);
+        fields
+    }
+
+    pub fn field(&self, kind: &str, field: &str) -> Option<&Field<'a>> {
+        self.fields.get(kind).and_then(|fs| fs.get(field))
+    }
+
+    /// The children of a kind of node that aren't in any field
+    pub fn children_of(&self, kind: &str) -> Option<&Children<'a>> {
+        self.contents.get(kind)
+    }
+
+    /// The kinds of nodes that can appear in a field. These may be supertypes
+    /// (e.g., `_expression`).
+    pub fn allowed_in_field(&self, kind: &str, field: &str) -> Vec<&str> {
+        self.field(kind, field)
+            .into_iter()
+            .flat_map(|f| f.types.iter().map(|t| t.ty.as_ref()))
+            .collect()
+    }
+
+    /// Whether a kind of node can appear as a child of another, either in a
+    /// field or not
+    pub fn can_contain(&self, parent: &str, child: &str) -> bool {
+        let in_fields = self
+            .fields
+            .get(parent)
+            .into_iter()
+            .flat_map(|fs| fs.values())
+            .flat_map(|f| &f.types);
+        let in_contents = self.contents.get(parent).into_iter().flat_map(|c| &c.types);
+        in_fields
+            .chain(in_contents)
+            .any(|t| self.is_descendant_of(child, &t.ty))
+    }
+}
+
+/// Levenshtein distance
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut prev: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut cur = vec![i + 1; b.len() + 1];
+        for (j, cb) in b.iter().enumerate() {
+            let sub = prev[j] + usize::from(ca != *cb);
+            cur[j + 1] = sub.min(prev[j + 1] + 1).min(cur[j] + 1);
+        }
+        prev = cur;
+    }
+    prev[b.len()]
 }
 
 #[cfg(test)]
@@ -108,4 +203,40 @@ mod tests {
         assert!(!nt.is_child_of("_expression", "empty_statement"));
         assert!(!nt.is_child_of("empty_statement", "_expression"));
     }
+
+    #[test]
+    fn test_fields() {
+        let nt = NodeTypes::new(tree_sitter_rust::NODE_TYPES).unwrap();
+        let fields: Vec<_> = nt
+            .fields_of("let_declaration")
+            .into_iter()
+            .map(|(name, _)| name)
+            .collect();
+        assert_eq!(vec!["alternative", "pattern", "type", "value"], fields);
+        let pattern = nt.field("let_declaration", "pattern").unwrap();
+        assert!(pattern.required && !pattern.multiple);
+        assert!(!nt.field("let_declaration", "value").unwrap().required);
+        assert_eq!(
+            vec!["_expression"],
+            nt.allowed_in_field("let_declaration", "value")
+        );
+        assert!(nt.allowed_in_field("let_declaration", "nope").is_empty());
+        assert!(nt.children_of("let_declaration").is_some());
+        assert!(nt.can_contain("let_declaration", "call_expression"));
+        assert!(nt.can_contain("let_declaration", "mutable_specifier"));
+        assert!(!nt.can_contain("let_declaration", "function_item"));
+        assert!(nt.fields_of("identifier").is_empty());
+    }
+
+    #[test]
+    fn test_suggest() {
+        let nt = NodeTypes::new(tree_sitter_rust::NODE_TYPES).unwrap();
+        assert!(nt.is_kind("identifier"));
+        assert!(nt.is_kind("_expression"));
+        assert!(!nt.is_kind("identfier"));
+        assert_eq!(Some("identifier"), nt.suggest("identfier"));
+        assert_eq!(Some("_expression"), nt.suggest("_expresion"));
+        assert_eq!(None, nt.suggest("xyzzy_quux"));
+        assert_eq!(3, edit_distance("kitten", "sitting"));
+    }
 }
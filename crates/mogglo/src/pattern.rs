@@ -1,12 +1,27 @@
-use std::collections::{HashMap, HashSet};
+use std::{
+    cell::RefCell,
+    collections::{HashMap, HashSet},
+    iter::Peekable,
+    ops::Range,
+    str::Chars,
+};
 
-use rlua::Lua;
+use regex::Regex;
+use rlua::{
+    prelude::{LuaContext, LuaError},
+    Table,
+};
 use tree_sitter::{Language, Node, Tree};
 
 use crate::{
+    dump::dump_with,
     env::{Env, Metavar},
-    lua::{eval_lua, eval_lua_scope, node::LuaNode, pattern::LuaPattern, LuaData},
+    lua::{
+        check_kinds, env as lua_env, eval_lua, eval_lua_scope, node::LuaNode, pattern::LuaPattern,
+        LuaData, LuaState,
+    },
     node_types::NodeTypes,
+    trace::{Event, Trace},
 };
 
 pub(crate) fn parse(language: Language, code: &str) -> Tree {
@@ -26,7 +41,8 @@ pub struct TmpVar(String);
 #[derive(Clone, Debug, Eq, Hash, PartialEq)]
 pub enum FindExpr {
     Anonymous,
-    Ellipsis,
+    /// `$..`, or `$..x` which binds the sibling nodes it matches
+    Ellipsis(Option<Metavar>),
     Metavar(Metavar),
     Lua(LuaCode),
 }
@@ -40,18 +56,136 @@ impl FindExpr {
             return Self::Anonymous;
         }
         if s == Self::ELLIPSIS {
-            return Self::Ellipsis;
+            return Self::Ellipsis(None);
         }
         Self::Metavar(Metavar(s))
     }
 }
 
+/// How a match relates to the nodes matched by another pattern
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum Relation {
+    /// The match is inside a node that matches the pattern
+    Inside,
+    /// The match isn't inside any node that matches the pattern
+    NotInside,
+    /// The match contains a node that matches the pattern
+    Has,
+    /// The match doesn't contain any node that matches the pattern
+    NotHas,
+}
+
+/// A problem with a pattern, see [`Pattern::errors`]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct PatternError {
+    /// Byte range in the pattern
+    pub span: Range<usize>,
+    pub message: String,
+    /// E.g., a similar kind of node
+    pub help: Option<String>,
+    /// Whether the pattern failed to parse, rather than e.g. referring to a
+    /// kind of node that doesn't exist
+    pub syntax: bool,
+}
+
+impl PatternError {
+    pub(crate) fn unknown_kind(node_types: &NodeTypes, kind: &str, span: Range<usize>) -> Self {
+        Self {
+            span,
+            message: format!("Unknown kind of node: {kind}"),
+            help: node_types
+                .suggest(kind)
+                .map(|k| format!("Did you mean `{k}`?")),
+            syntax: false,
+        }
+    }
+
+    fn shift(mut self, offset: usize) -> Self {
+        self.span = self.span.start + offset..self.span.end + offset;
+        self
+    }
+}
+
+/// Where the parsed text came from in the pattern, for error messages. Parts
+/// of the text that aren't metavariables are copied from the pattern verbatim.
+#[derive(Debug, Default)]
+struct SourceMap {
+    /// Length of the code added before the pattern, see NOTE[expression-hack]
+    prefix: usize,
+    /// Ranges of each temporary variable in the text and in the pattern
+    vars: Vec<(TmpVar, Range<usize>, Range<usize>)>,
+}
+
+impl SourceMap {
+    /// Byte offset of the next character of the pattern
+    fn offset(pat: &str, peek: &Peekable<Chars>) -> usize {
+        pat.len() - peek.clone().map(char::len_utf8).sum::<usize>()
+    }
+
+    /// Record a temporary variable that was just added to the end of the text
+    fn push(&mut self, tvar: &TmpVar, text: &str, span: Range<usize>) {
+        let start = text.len() - tvar.0.len();
+        self.vars.push((tvar.clone(), start..text.len(), span));
+    }
+
+    fn span(&self, tvar: &TmpVar) -> Option<Range<usize>> {
+        self.vars
+            .iter()
+            .find(|(t, _, _)| t == tvar)
+            .map(|(_, _, span)| span.clone())
+    }
+
+    /// Map a byte offset in the text to one in the pattern
+    fn to_pattern(&self, offset: usize, pat: &str) -> usize {
+        let offset = offset.saturating_sub(self.prefix);
+        let mapped = match self.vars.iter().rev().find(|(_, t, _)| t.start <= offset) {
+            None => offset,
+            Some((_, t, span)) if offset < t.end => span.start,
+            Some((_, t, span)) => span.end + (offset - t.end),
+        };
+        mapped.min(pat.len())
+    }
+}
+
+/// Where a pattern goes in a program, for patterns that aren't valid on their
+/// own (e.g., class members or match arms). See NOTE[expression-hack].
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct ParseContext<'a> {
+    /// Code that contains [`ParseContext::PLACEHOLDER`] where the pattern goes
+    pub template: Option<&'a str>,
+    /// Kind of the node to use as the pattern
+    pub kind: Option<&'a str>,
+}
+
+impl ParseContext<'_> {
+    pub const PLACEHOLDER: &'static str = "$PAT";
+}
+
 #[derive(Clone, Debug)]
 pub struct Pattern<'nts> {
     exprs: HashMap<TmpVar, FindExpr>,
+    /// Kinds of nodes that metavariables can match (`$x:kind`)
+    kinds: HashMap<TmpVar, String>,
+    /// Regular expressions that the text of metavariables must match
+    /// (`$/regex/`, `$x/regex/`)
+    regexes: HashMap<TmpVar, Result<Regex, regex::Error>>,
+    /// Patterns, any of which can match in place of a metavariable
+    /// (`${| pattern $| pattern |}`)
+    alternatives: HashMap<TmpVar, Vec<Pattern<'nts>>>,
     lang: Language,
     node_types: &'nts NodeTypes<'nts>,
+    /// Patterns that the ancestors or descendants of a match must (not) match
+    relations: Vec<(Relation, Pattern<'nts>)>,
     root_id: usize,
+    /// Whether children must match exactly, see [`Pattern::strict`]
+    strict: bool,
+    /// Problems found while parsing, see [`Pattern::errors`]
+    errors: Vec<PatternError>,
+    /// Patterns from Lua code, see [`Pattern::with_sub_pattern`]
+    sub_patterns: RefCell<HashMap<String, Result<Pattern<'nts>, String>>>,
+    /// Errors in Lua code that were already reported, see
+    /// [`Pattern::match_expr`]
+    lua_errors: RefCell<HashSet<String>>,
     text: String,
     tree: Tree,
     r#where: Vec<LuaCode>,
@@ -82,13 +216,6 @@ impl<'tree> Goal<'tree> {
         })
     }
 
-    fn next_sibling(&self) -> Option<Self> {
-        self.node.next_sibling().map(|node| Self {
-            node,
-            text: self.text,
-        })
-    }
-
     fn _parent(&self) -> Option<Self> {
         self.node.parent().map(|node| Self {
             node,
@@ -114,13 +241,6 @@ impl<'tree> Candidate<'tree> {
             text: self.text,
         }
     }
-
-    fn next_sibling(&self) -> Option<Self> {
-        self.node.next_sibling().map(|node| Self {
-            node,
-            text: self.text,
-        })
-    }
 }
 
 #[derive(Clone, Debug, Eq, PartialEq)]
@@ -134,21 +254,126 @@ impl<'nts> Pattern<'nts> {
         TmpVar(format!("mogglo_tmp_var_{i}"))
     }
 
-    // TODO: Disallow anything after ellipses
+    /// Parse the kind after a metavariable (`:kind`), if there is one
+    fn parse_metavar_kind(peek: &mut Peekable<Chars>) -> Option<String> {
+        let mut ahead = peek.clone();
+        if ahead.next() != Some(':') {
+            return None;
+        }
+        let kind: String = ahead
+            .take_while(|c| c.is_ascii_alphanumeric() || *c == '_')
+            .collect();
+        if !kind.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_') {
+            return None;
+        }
+        peek.nth(kind.len());
+        Some(kind)
+    }
+
+    /// Parse a regular expression between slashes (`/regex/`), if there is one.
+    /// Slashes in the regex are escaped with a backslash.
+    fn parse_regex(peek: &mut Peekable<Chars>) -> Option<String> {
+        let mut ahead = peek.clone();
+        if ahead.next() != Some('/') {
+            return None;
+        }
+        let mut regex = String::new();
+        let mut len = 1;
+        loop {
+            let c = ahead.next()?;
+            len += 1;
+            match c {
+                '/' => break,
+                '\\' if ahead.peek() == Some(&'/') => {
+                    ahead.next();
+                    len += 1;
+                    regex.push('/');
+                }
+                c => regex.push(c),
+            }
+        }
+        peek.nth(len - 1);
+        Some(regex)
+    }
+
+    /// Parse alternatives (`{| pattern $| pattern |}`), if there are some
+    fn parse_alternatives(peek: &mut Peekable<Chars>) -> Option<Vec<String>> {
+        let mut ahead = peek.clone();
+        if ahead.next() != Some('{') || ahead.next() != Some('|') {
+            return None;
+        }
+        let mut alts = Vec::new();
+        let mut alt = String::new();
+        let mut depth = 0;
+        let mut len = 2;
+        loop {
+            let c = ahead.next()?;
+            len += 1;
+            let next = ahead.peek().copied();
+            if c == '$' && next == Some('|') && depth == 0 {
+                ahead.next();
+                len += 1;
+                alts.push(alt.trim().to_string());
+                alt = String::new();
+                continue;
+            }
+            if c == '|' && next == Some('}') {
+                if depth == 0 {
+                    ahead.next();
+                    len += 1;
+                    alts.push(alt.trim().to_string());
+                    break;
+                }
+                depth -= 1;
+            } else if c == '$' && next == Some('{') {
+                let mut nested = ahead.clone();
+                nested.next();
+                if nested.next() == Some('|') {
+                    depth += 1;
+                }
+            }
+            alt.push(c);
+        }
+        peek.nth(len - 1);
+        Some(alts)
+    }
+
     fn parse_from(
         lang: Language,
         node_types: &'nts NodeTypes<'nts>,
         pat: String,
         mut vars: usize,
-        unwrap_until: Option<&str>,
+        ctx: ParseContext,
     ) -> Self {
         let mut peek = pat.chars().peekable();
         let mut nest = 0;
         let mut code = String::new();
         let mut text = String::new();
         let mut exprs = HashMap::new();
+        let mut kinds = HashMap::new();
+        let mut regexes = HashMap::new();
+        let mut alternatives = HashMap::new();
+        let mut source_map = SourceMap::default();
+        // Where the current metavariable or Lua code started in the pattern
+        let mut start = 0;
         while let Some(current) = peek.next() {
+            if nest == 0 {
+                start = SourceMap::offset(&pat, &peek) - current.len_utf8();
+            }
             if current == '$' {
+                // ${| pattern $| pattern |}
+                if nest == 0 {
+                    if let Some(alts) = Self::parse_alternatives(&mut peek) {
+                        let tvar = Self::meta(vars);
+                        vars += 1;
+                        text += &tvar.0;
+                        alternatives.insert(tvar.clone(), alts);
+                        source_map.push(&tvar, &text, start..SourceMap::offset(&pat, &peek));
+                        exprs.insert(tvar, FindExpr::Anonymous);
+                        continue;
+                    }
+                }
+
                 // ${{code}}
                 if peek.next_if_eq(&'{').is_some() && peek.next_if_eq(&'{').is_some() {
                     if nest > 0 {
@@ -161,20 +386,44 @@ impl<'nts> Pattern<'nts> {
                     continue;
                 }
 
+                // $/regex/
+                if let Some(regex) = Self::parse_regex(&mut peek) {
+                    let tvar = Self::meta(vars);
+                    vars += 1;
+                    text += &tvar.0;
+                    regexes.insert(tvar.clone(), Regex::new(&regex));
+                    source_map.push(&tvar, &text, start..SourceMap::offset(&pat, &peek));
+                    exprs.insert(tvar, FindExpr::Anonymous);
+                    continue;
+                }
+
                 // $_
                 if peek.next_if_eq(&'_').is_some() {
                     let tvar = Self::meta(vars);
                     vars += 1;
                     text += &tvar.0;
+                    if let Some(kind) = Self::parse_metavar_kind(&mut peek) {
+                        kinds.insert(tvar.clone(), kind);
+                    }
+                    source_map.push(&tvar, &text, start..SourceMap::offset(&pat, &peek));
                     exprs.insert(tvar, FindExpr::Anonymous);
+                    continue;
                 }
 
-                // $..
+                // $.. or $..x
                 if peek.next_if_eq(&'.').is_some() && peek.next_if_eq(&'.').is_some() {
+                    let mvar_name: String =
+                        peek.clone().take_while(char::is_ascii_alphabetic).collect();
+                    if !mvar_name.is_empty() {
+                        peek.nth(mvar_name.len() - 1);
+                    }
                     let tvar = Self::meta(vars);
                     vars += 1;
                     text += &tvar.0;
-                    exprs.insert(tvar, FindExpr::Ellipsis);
+                    let name = Some(Metavar(mvar_name)).filter(|m| !m.0.is_empty());
+                    source_map.push(&tvar, &text, start..SourceMap::offset(&pat, &peek));
+                    exprs.insert(tvar, FindExpr::Ellipsis(name));
+                    continue;
                 }
 
                 // $x
@@ -187,6 +436,20 @@ impl<'nts> Pattern<'nts> {
                     let tvar = Self::meta(vars);
                     vars += 1;
                     text += &tvar.0;
+                    if let Some(kind) = Self::parse_metavar_kind(&mut peek) {
+                        kinds.insert(tvar.clone(), kind);
+                    }
+                    // $x/regex/, unless the slashes are division, e.g.,
+                    // `$x/2 + $y/3`
+                    let mut ahead = peek.clone();
+                    if let Some(regex) = Self::parse_regex(&mut ahead) {
+                        let operand = |c: &char| c.is_alphanumeric() || "_$'\"".contains(*c);
+                        if ahead.peek().filter(|c| operand(c)).is_none() {
+                            peek = ahead;
+                            regexes.insert(tvar.clone(), Regex::new(&regex));
+                        }
+                    }
+                    source_map.push(&tvar, &text, start..SourceMap::offset(&pat, &peek));
                     exprs.insert(tvar, FindExpr::Metavar(Metavar(mvar_name)));
                     continue;
                 }
@@ -196,6 +459,7 @@ impl<'nts> Pattern<'nts> {
                     let tvar = Self::meta(vars);
                     vars += 1;
                     text += &tvar.0;
+                    source_map.push(&tvar, &text, start..SourceMap::offset(&pat, &peek));
                     exprs.insert(tvar, FindExpr::Lua(LuaCode(code)));
                     code = String::new();
                 } else {
@@ -218,53 +482,239 @@ impl<'nts> Pattern<'nts> {
         // it into an expression when transforming it into a goal.
         //
         // Weggli appears to work similarly by default.
-        let mut tree = parse(lang, &text);
-        if tree.root_node().has_error() {
-            text = format!("{{ {text} }}");
+        let whole = |pat: &str, message: String| PatternError {
+            span: 0..pat.len(),
+            message,
+            help: None,
+            syntax: false,
+        };
+        let mut errors = Vec::new();
+        let tree;
+        let root_id;
+        if let Some(template) = ctx.template {
+            // The pattern is the smallest node that spans its text, or its
+            // nearest ancestor of the given kind.
+            let (before, after) = template
+                .split_once(ParseContext::PLACEHOLDER)
+                .unwrap_or_else(|| {
+                    let msg = format!("Context doesn't contain {}", ParseContext::PLACEHOLDER);
+                    errors.push(whole(&pat, msg));
+                    (template, "")
+                });
+            source_map.prefix = before.len();
+            let start = before.len() + text.len() - text.trim_start().len();
+            let end = before.len() + text.trim_end().len();
+            text = format!("{before}{text}{after}");
             tree = parse(lang, &text);
-            if tree.root_node().has_error() {
-                text = format!("{text};");
-                tree = parse(lang, &text);
-                if tree.root_node().has_error() {
-                    eprintln!("[WARN] Parse error in pattern!");
+            let mut root = tree
+                .root_node()
+                .descendant_for_byte_range(start, end)
+                .unwrap_or_else(|| tree.root_node());
+            if let Some(kind) = ctx.kind.filter(|k| *k != root.kind()) {
+                match ancestors(root).into_iter().find(|n| n.kind() == kind) {
+                    Some(n) => root = n,
+                    None if !node_types.is_kind(kind) => {
+                        errors.push(PatternError::unknown_kind(node_types, kind, 0..pat.len()))
+                    }
+                    None => errors.push(whole(
+                        &pat,
+                        format!("No node of kind {kind} contains the pattern"),
+                    )),
                 }
             }
+            root_id = root.id();
+        } else {
+            let mut parsed = parse(lang, &text);
+            if parsed.root_node().has_error() {
+                text = format!("{{ {text} }}");
+                source_map.prefix = 2;
+                parsed = parse(lang, &text);
+                if parsed.root_node().has_error() {
+                    text = format!("{text};");
+                    parsed = parse(lang, &text);
+                }
+            }
+            tree = parsed;
+            let mut root = tree.root_node();
+            // Get rid of top-level "program" node
+            if root.child_count() == 1 {
+                root = root.child(0).unwrap();
+            }
+            // See NOTE[expression-hack]
+            while root.named_child_count() == 1 {
+                if Some(root.kind()) == ctx.kind {
+                    break;
+                }
+                root = root.named_child(0).unwrap();
+            }
+            root_id = root.id();
+        }
+
+        for node in syntax_errors(tree.root_node()) {
+            let start = source_map.to_pattern(node.start_byte(), &pat);
+            let end = source_map.to_pattern(node.end_byte(), &pat);
+            // Missing nodes are empty, point at the next character instead
+            let end = end.max((start + 1).min(pat.len()));
+            errors.push(PatternError {
+                span: start.min(end)..end,
+                message: if node.is_missing() {
+                    format!("Missing `{}`", node.kind())
+                } else {
+                    "Failed to parse pattern".to_string()
+                },
+                help: None,
+                syntax: true,
+            });
+        }
+        for (tvar, kind) in &kinds {
+            if node_types.is_kind(kind) {
+                continue;
+            }
+            let span = source_map.span(tvar).unwrap_or_default();
+            let span = pat[span.clone()]
+                .find(&format!(":{kind}"))
+                .map_or(span.clone(), |i| {
+                    span.start + i + 1..span.start + i + 1 + kind.len()
+                });
+            errors.push(PatternError::unknown_kind(node_types, kind, span));
         }
-        let mut root = tree.root_node();
-        // Get rid of top-level "program" node
-        if root.child_count() == 1 {
-            root = root.child(0).unwrap();
+        for (tvar, regex) in &regexes {
+            let span = source_map.span(tvar).unwrap_or_default();
+            match regex {
+                Err(e) => errors.push(PatternError {
+                    span,
+                    message: "Invalid regular expression".to_string(),
+                    help: Some(e.to_string()),
+                    syntax: false,
+                }),
+                Ok(r) if has_metavar(r.as_str()) => errors.push(PatternError {
+                    span,
+                    message: "Regular expression contains a metavariable".to_string(),
+                    help: Some("For division, put spaces around `/`".to_string()),
+                    syntax: false,
+                }),
+                Ok(_) => (),
+            }
         }
-        // See NOTE[expression-hack]
-        while root.named_child_count() == 1 {
-            if Some(root.kind()) == unwrap_until {
-                break;
+        for (tvar, expr) in &exprs {
+            if let FindExpr::Lua(LuaCode(code)) = expr {
+                // Skip the `${{`
+                let offset = source_map.span(tvar).unwrap_or_default().start + 3;
+                errors.extend(
+                    check_kinds(node_types, code)
+                        .into_iter()
+                        .map(|e| e.shift(offset)),
+                );
             }
-            root = root.named_child(0).unwrap();
         }
 
+        let alternatives = alternatives
+            .into_iter()
+            .map(|(tvar, alts)| {
+                let span = source_map.span(&tvar).unwrap_or_default();
+                let alts = alts
+                    .into_iter()
+                    .map(|alt| {
+                        let offset = pat[span.clone()].find(&alt).unwrap_or(0) + span.start;
+                        let pat =
+                            Self::parse_from(lang, node_types, alt, vars, ParseContext::default());
+                        vars += pat.exprs.len();
+                        errors.extend(pat.errors.iter().cloned().map(|e| e.shift(offset)));
+                        pat
+                    })
+                    .collect();
+                (tvar, alts)
+            })
+            .collect();
+        errors.sort_by_key(|e| (e.span.start, e.span.end));
+
         Self {
             exprs,
+            kinds,
+            regexes,
+            alternatives,
             lang,
             node_types,
-            root_id: root.id(),
+            root_id,
+            relations: Vec::new(),
+            strict: false,
+            errors,
+            sub_patterns: RefCell::default(),
+            lua_errors: RefCell::default(),
             text,
             tree,
             r#where: Vec::new(),
         }
     }
 
+    /// Use a pattern from Lua code, which mustn't reuse this pattern's
+    /// temporary variables. Each is only parsed once, even though the code
+    /// runs for many candidates. Problems with it are errors in the Lua code.
+    fn with_sub_pattern<T>(&self, pat: String, f: impl FnOnce(&Self) -> T) -> Result<T, LuaError> {
+        if !self.sub_patterns.borrow().contains_key(&pat) {
+            let mut sub = Self::parse_from(
+                self.lang,
+                self.node_types,
+                pat.clone(),
+                self.exprs.len(),
+                ParseContext::default(),
+            );
+            sub.strict(self.strict);
+            let sub = match sub.errors.first() {
+                None => Ok(sub),
+                Some(e) => Err(format!("{} in sub-pattern: {pat}", e.message)),
+            };
+            self.sub_patterns.borrow_mut().insert(pat.clone(), sub);
+        }
+        // Snippets in the sub-pattern only use its own sub-patterns, so this
+        // isn't borrowed mutably while matching.
+        match &self.sub_patterns.borrow()[&pat] {
+            Ok(sub) => Ok(f(sub)),
+            Err(e) => Err(LuaError::RuntimeError(e.clone())),
+        }
+    }
+
+    /// A pattern that matches wherever any of the given patterns do
+    pub fn alternatives(
+        lang: Language,
+        node_types: &'nts NodeTypes<'nts>,
+        pats: Vec<String>,
+        ctx: ParseContext,
+    ) -> Self {
+        let mut pat = Self::parse(lang, node_types, "$_".to_string());
+        let alts = pats
+            .into_iter()
+            .map(|p| Self::parse_from(lang, node_types, p, 1, ctx))
+            .collect();
+        pat.alternatives.insert(Self::meta(0), alts);
+        pat
+    }
+
     pub fn parse_kind(
         lang: Language,
         node_types: &'nts NodeTypes<'nts>,
         pat: String,
         kind: &str,
     ) -> Self {
-        Self::parse_from(lang, node_types, pat, 0, Some(kind))
+        let ctx = ParseContext {
+            template: None,
+            kind: Some(kind),
+        };
+        Self::parse_from(lang, node_types, pat, 0, ctx)
+    }
+
+    /// Parse a pattern in a context, see [`ParseContext`]
+    pub fn parse_in(
+        lang: Language,
+        node_types: &'nts NodeTypes<'nts>,
+        pat: String,
+        ctx: ParseContext,
+    ) -> Self {
+        Self::parse_from(lang, node_types, pat, 0, ctx)
     }
 
     pub fn parse(lang: Language, node_types: &'nts NodeTypes<'nts>, pat: String) -> Self {
-        Self::parse_from(lang, node_types, pat, 0, None)
+        Self::parse_from(lang, node_types, pat, 0, ParseContext::default())
     }
 
     fn match_leaf_node(goal: Goal, candidate: Candidate) -> bool {
@@ -274,7 +724,8 @@ impl<'nts> Pattern<'nts> {
 
     fn match_plain_node<'tree>(
         &self,
-        lua: &Lua,
+        lua: &LuaState,
+        trace: &Trace,
         mut env: Env<'tree>,
         goal: Goal,
         candidate: Candidate<'tree>,
@@ -287,6 +738,11 @@ impl<'nts> Pattern<'nts> {
             // candidate: { x; }
             // goal: { }
             if candidate_count != 0 {
+                trace.record(
+                    || self.show(goal),
+                    candidate.node.byte_range(),
+                    || Event::Children,
+                );
                 return None;
             }
             // ex:
@@ -301,60 +757,101 @@ impl<'nts> Pattern<'nts> {
             // ex:
             // candidate: x
             // goal: y
+            trace.record(
+                || self.show(goal),
+                candidate.node.byte_range(),
+                || Event::Text,
+            );
             return None;
         }
 
         if goal.node.kind_id() == candidate.node.kind_id() {
-            let mut goal_child = goal.child(0);
-            let mut candidate_child = candidate.child(0);
-            loop {
-                if let Some(FindExpr::Ellipsis) =
-                    self.exprs.get(&TmpVar(goal_child.as_str().to_string()))
-                {
-                    return Some(Match {
+            // Children are paired up with children in the same field (or with
+            // children that aren't in any field), so that e.g. a pattern for a
+            // function's return type doesn't match its parameters.
+            let goals: Vec<_> = (0..goal_count)
+                .map(|i| goal.child(i))
+                .zip(field_names(goal.node))
+                .collect();
+            let candidates: Vec<_> = (0..candidate_count)
+                .map(|i| candidate.child(i))
+                .zip(field_names(candidate.node))
+                .collect();
+            if self.strict || goals.iter().any(|(g, _)| self.ellipsis(*g).is_some()) {
+                // Comments and other extras can appear anywhere, so they
+                // aren't counted as siblings
+                let goals: Vec<_> = goals
+                    .into_iter()
+                    .filter(|(g, _)| !g.node.is_extra())
+                    .collect();
+                let candidates: Vec<_> = candidates
+                    .into_iter()
+                    .filter(|(c, _)| !c.node.is_extra())
+                    .collect();
+                return self
+                    .match_siblings(
+                        lua,
+                        trace,
+                        env,
+                        &goals,
+                        &candidates,
+                        candidate.node.end_byte(),
+                        self.strict,
+                    )
+                    .map(|env| Match {
                         env,
                         root: candidate.node,
                     });
-                }
-                if let Some(m) =
-                    self.match_node_internal(lua, env.clone(), goal_child, candidate_child)
-                {
-                    env.extend(m.env);
-                    match (goal_child.next_sibling(), candidate_child.next_sibling()) {
-                        (Some(gnext), Some(cnext)) => {
-                            goal_child = gnext;
-                            candidate_child = cnext;
-                        }
-                        (None, Some(_)) => {
-                            return Some(Match {
-                                env,
-                                root: candidate.node,
-                            })
-                        }
-                        (Some(gnext), None) => {
-                            // Might be an ellipsis
-                            goal_child = gnext;
-                        }
-                        (None, None) => {
-                            return Some(Match {
-                                env,
-                                root: candidate.node,
-                            })
-                        }
+            }
+            let mut candidates = candidates.into_iter();
+            for (goal_child, field) in goals {
+                loop {
+                    let Some((candidate_child, candidate_field)) = candidates.next() else {
+                        let event = || Event::Missing {
+                            field: field.map(String::from),
+                        };
+                        trace.record(|| self.show(goal_child), candidate.node.byte_range(), event);
+                        return None;
+                    };
+                    if field != candidate_field {
+                        continue;
                     }
-                } else {
-                    match candidate_child.next_sibling() {
-                        None => return None,
-                        Some(cnext) => candidate_child = cnext,
+                    if let Some(m) = trace.nested(|| {
+                        self.match_node_internal(
+                            lua,
+                            trace,
+                            env.clone(),
+                            goal_child,
+                            candidate_child,
+                        )
+                    }) {
+                        env.extend(m.env);
+                        break;
                     }
                 }
             }
+            Some(Match {
+                env,
+                root: candidate.node,
+            })
+        } else if self.strict {
+            trace.record(
+                || self.show(goal),
+                candidate.node.byte_range(),
+                || self.kind(goal, candidate),
+            );
+            None
         } else {
+            trace.record(
+                || self.show(goal),
+                candidate.node.byte_range(),
+                || self.kind(goal, candidate),
+            );
             // Match goal with any child
             for i in 0..candidate.node.child_count() {
                 // TODO: rm clone
                 if let Some(m) =
-                    self.match_node_internal(lua, env.clone(), goal, candidate.child(i))
+                    self.match_node_internal(lua, trace, env.clone(), goal, candidate.child(i))
                 {
                     return Some(m);
                 }
@@ -363,9 +860,134 @@ impl<'nts> Pattern<'nts> {
         }
     }
 
+    /// If the goal is an ellipsis, or an ellipsis followed by a terminator like
+    /// `;`, its name
+    fn ellipsis(&self, goal: Goal) -> Option<Option<&Metavar>> {
+        let ellipsis = |g: Goal| match self.exprs.get(&TmpVar(g.as_str().to_string())) {
+            Some(FindExpr::Ellipsis(name)) => Some(name.as_ref()),
+            _ => None,
+        };
+        if goal.node.child_count() == 2 && !goal.child(1).node.is_named() {
+            return ellipsis(goal.child(0));
+        }
+        ellipsis(goal)
+    }
+
+    /// Whether the siblings bound to an earlier occurrence of a named ellipsis
+    /// are structurally equal to the candidates, like repeated metavariables
+    fn equal_siblings<'tree>(
+        &self,
+        lua: &LuaState,
+        bound: &[Node<'tree>],
+        candidates: &[(Candidate<'tree>, Option<&str>)],
+    ) -> bool {
+        bound.len() == candidates.len()
+            && bound.iter().zip(candidates).all(|(node, (candidate, _))| {
+                let goal = Goal {
+                    node: *node,
+                    text: candidate.text,
+                };
+                let trace = Trace::default();
+                self.match_plain_node(lua, &trace, Env::default(), goal, *candidate)
+                    .is_some()
+            })
+    }
+
+    /// Match goals against candidates in order, where ellipses match any
+    /// number of candidates. Earlier ellipses match as few candidates as
+    /// possible. Other goals only match candidates in the same field.
+    ///
+    /// Unless `anchored`, a goal skips candidates until one matches, as when
+    /// there are no ellipses. The goals after an ellipsis are anchored: they
+    /// must match the candidates right after the ones the ellipsis matched, up
+    /// to the last candidate. In strict mode, all goals are anchored. `end` is
+    /// where the candidates end, for tracing missing ones.
+    #[allow(clippy::too_many_arguments)]
+    fn match_siblings<'tree>(
+        &self,
+        lua: &LuaState,
+        trace: &Trace,
+        env: Env<'tree>,
+        goals: &[(Goal, Option<&str>)],
+        candidates: &[(Candidate<'tree>, Option<&str>)],
+        end: usize,
+        anchored: bool,
+    ) -> Option<Env<'tree>> {
+        let Some(((goal, field), goals)) = goals.split_first() else {
+            if !anchored {
+                return Some(env);
+            }
+            if let Some((first, _)) = candidates.first() {
+                let last = candidates.last().unwrap().0;
+                let range = first.node.start_byte()..last.node.end_byte();
+                trace.record(String::new, range, || Event::Extra);
+                return None;
+            }
+            return Some(env);
+        };
+        if let Some(name) = self.ellipsis(*goal) {
+            for n in 0..=candidates.len() {
+                let (skipped, rest) = candidates.split_at(n);
+                let mut env = env.clone();
+                if let Some(mvar) = name {
+                    match env.1.get(mvar) {
+                        Some(bound) if !self.equal_siblings(lua, bound, skipped) => continue,
+                        Some(_) => (),
+                        None => env.insert_siblings(
+                            mvar.clone(),
+                            skipped.iter().map(|(c, _)| c.node).collect(),
+                        ),
+                    }
+                }
+                if let Some(env) = self.match_siblings(lua, trace, env, goals, rest, end, true) {
+                    return Some(env);
+                }
+            }
+            return None;
+        }
+        if !anchored {
+            // Like `match_plain_node` without ellipses, the first candidate
+            // that matches is the one, there's no backtracking
+            for (i, (candidate, candidate_field)) in candidates.iter().enumerate() {
+                if field != candidate_field {
+                    continue;
+                }
+                let m = trace.nested(|| {
+                    self.match_node_internal(lua, trace, env.clone(), *goal, *candidate)
+                });
+                if let Some(m) = m {
+                    let rest = &candidates[i + 1..];
+                    return self.match_siblings(lua, trace, m.env, goals, rest, end, false);
+                }
+            }
+            let event = || Event::Missing {
+                field: field.map(String::from),
+            };
+            trace.record(|| self.show(*goal), end..end, event);
+            return None;
+        }
+        let Some(((candidate, candidate_field), candidates)) = candidates.split_first() else {
+            let event = || Event::Missing {
+                field: field.map(String::from),
+            };
+            trace.record(|| self.show(*goal), end..end, event);
+            return None;
+        };
+        if field != candidate_field {
+            let event = || Event::Missing {
+                field: field.map(String::from),
+            };
+            trace.record(|| self.show(*goal), candidate.node.byte_range(), event);
+            return None;
+        }
+        let m = trace.nested(|| self.match_node_internal(lua, trace, env, *goal, *candidate))?;
+        self.match_siblings(lua, trace, m.env, goals, candidates, end, true)
+    }
+
     fn match_expr<'tree>(
         &self,
-        lua: &Lua,
+        lua: &LuaState,
+        trace: &Trace,
         mut env: Env<'tree>,
         expr: &FindExpr,
         candidate: Candidate<'tree>,
@@ -375,7 +997,7 @@ impl<'nts> Pattern<'nts> {
                 env,
                 root: candidate.node,
             }),
-            FindExpr::Ellipsis => panic!("Unhandled ellipsis"),
+            FindExpr::Ellipsis(_) => panic!("Unhandled ellipsis"),
             FindExpr::Metavar(m) => match env.0.get(m) {
                 None => {
                     env.insert(m.clone(), candidate.node);
@@ -392,7 +1014,26 @@ impl<'nts> Pattern<'nts> {
                             node: *goal,
                             text: candidate.text,
                         };
-                        let mch = self.match_plain_node(lua, extended.clone(), goal, candidate)?;
+                        // The earlier occurrence isn't part of the pattern,
+                        // so don't trace the comparison itself
+                        let Some(mch) = self.match_plain_node(
+                            lua,
+                            &Trace::default(),
+                            extended.clone(),
+                            goal,
+                            candidate,
+                        ) else {
+                            let event = || Event::Metavar {
+                                name: m.0.clone(),
+                                bound: goal.node.byte_range(),
+                            };
+                            trace.record(
+                                || format!("${}", m.0),
+                                candidate.node.byte_range(),
+                                event,
+                            );
+                            return None;
+                        };
                         extended.insert(m.clone(), mch.root);
                     }
                     Some(Match {
@@ -407,10 +1048,60 @@ impl<'nts> Pattern<'nts> {
                     node_types: self.node_types,
                     text: candidate.text,
                 };
-                let mut binds = Env::default();
+                let binds = RefCell::new(Env::default());
+                // Sub-patterns for the Lua functions below, see `doc/api.md`.
+                // Snippets in them get their own globals, so they don't
+                // clobber `focus`, `bind`, etc. of this one.
+                let match_pat = |p: String| {
+                    self.with_sub_pattern(p, |pat| {
+                        let trace = &Trace::default();
+                        lua.fresh(|| {
+                            pat.match_node_internal(
+                                lua,
+                                trace,
+                                env.clone(),
+                                pat.to_goal(),
+                                candidate,
+                            )
+                            .map(|m| m.env)
+                        })
+                    })
+                };
+                let pmatch_pat = |p: &LuaPattern, n: &LuaNode| {
+                    let candidate = Candidate {
+                        node: n.node,
+                        text: n.text,
+                    };
+                    let trace = &Trace::default();
+                    lua.fresh(|| {
+                        p.0.match_node_internal(lua, trace, env.clone(), p.0.to_goal(), candidate)
+                            .map(|m| m.env)
+                    })
+                };
+                let rec_pat = |p: String| {
+                    self.with_sub_pattern(p, |pat| {
+                        let trace = &Trace::default();
+                        pat.matches_internal(
+                            lua,
+                            trace,
+                            candidate.text,
+                            candidate.node,
+                            &env,
+                            true,
+                            Some(1),
+                        )
+                        .pop()
+                        .map(|m| m.env)
+                    })
+                };
                 // TODO: Handle errors
-                let matched = lua.context(|lua_ctx| {
-                    let loaded = match lua_ctx.load(code).set_name("lua code") {
+                let matched = lua.get().context(|lua_ctx| {
+                    let globals = lua_env(lua_ctx)?;
+                    let loaded = match lua_ctx
+                        .load(code)
+                        .set_name("lua code")
+                        .and_then(|c| c.set_environment(globals.clone()))
+                    {
                         Err(e) => {
                             eprintln!("Bad Lua code: {code}");
                             return Err(e);
@@ -418,99 +1109,92 @@ impl<'nts> Pattern<'nts> {
                         Ok(l) => l,
                     };
                     lua_ctx.scope(|scope| {
-                        let globals = lua_ctx.globals();
                         globals.set("focus", LuaNode::new(candidate.node, candidate.text))?;
                         globals.set("t", candidate.as_str())?;
                         globals.set(
                             "bind",
-                            scope.create_function_mut(|_, m: String| {
-                                binds.insert(Metavar(m), candidate.node);
+                            scope.create_function(|_, m: String| {
+                                binds.borrow_mut().insert(Metavar(m), candidate.node);
                                 Ok(())
                             })?,
                         )?;
-                        // TODO: Option to export metavariables
                         globals.set(
                             "match",
-                            scope.create_function(|_, p: String| {
-                                let pat = Pattern::parse_from(
-                                    self.lang,
-                                    self.node_types,
-                                    p,
-                                    self.exprs.len(),
-                                    None,
-                                );
-                                Ok(pat
-                                    .match_node_internal(lua, env.clone(), pat.to_goal(), candidate)
-                                    .is_some())
+                            scope.create_function(|_, p: String| Ok(match_pat(p)?.is_some()))?,
+                        )?;
+                        globals.set(
+                            "match_export",
+                            scope.create_function(|lua_ctx, p: String| {
+                                export(lua_ctx, &binds, match_pat(p)?, candidate.text)
                             })?,
                         )?;
 
                         globals.set(
                             "pat",
                             scope.create_function(|_, p: String| {
-                                let pat = Pattern::parse_from(
-                                    self.lang,
-                                    self.node_types,
-                                    p,
-                                    self.exprs.len(),
-                                    None,
-                                );
-                                Ok(LuaPattern::new(pat))
+                                self.with_sub_pattern(p, |pat| LuaPattern::new(pat.clone()))
                             })?,
                         )?;
 
                         globals.set(
                             "pmatch",
                             scope.create_function(|_, (p, n): (LuaPattern, LuaNode)| {
-                                Ok(p.0
-                                    .match_node_internal(
-                                        lua,
-                                        env.clone(),
-                                        p.0.to_goal(),
-                                        Candidate {
-                                            node: n.node,
-                                            text: n.text,
-                                        },
-                                    )
-                                    .is_some())
+                                Ok(pmatch_pat(&p, &n).is_some())
+                            })?,
+                        )?;
+                        globals.set(
+                            "pmatch_export",
+                            scope.create_function(|lua_ctx, (p, n): (LuaPattern, LuaNode)| {
+                                export(lua_ctx, &binds, pmatch_pat(&p, &n), candidate.text)
                             })?,
                         )?;
 
-                        // TODO: Option to export metavariables
                         globals.set(
                             "rec",
-                            scope.create_function(|_, p: String| {
-                                let pat = Pattern::parse_from(
-                                    self.lang,
-                                    self.node_types,
-                                    p,
-                                    self.exprs.len(),
-                                    None,
-                                );
-                                Ok(!pat
-                                    .matches_internal(
-                                        candidate.text,
-                                        candidate.node,
-                                        &env,
-                                        true,
-                                        Some(1),
-                                    )
-                                    .is_empty())
+                            scope.create_function(|_, p: String| Ok(rec_pat(p)?.is_some()))?,
+                        )?;
+                        globals.set(
+                            "rec_export",
+                            scope.create_function(|lua_ctx, p: String| {
+                                export(lua_ctx, &binds, rec_pat(p)?, candidate.text)
                             })?,
                         )?;
                         eval_lua_scope::<bool>(lua_ctx, scope, loaded, &data)
                     })
                 });
                 // TODO: Maybe check for collisions
-                env.extend(binds);
+                env.extend(binds.into_inner());
                 match matched {
                     Ok(true) => Some(Match {
                         env,
                         root: candidate.node,
                     }),
-                    Ok(false) => None,
+                    Ok(false) => {
+                        let event = || Event::Lua(code.clone());
+                        trace.record(
+                            || format!("${{{{{code}}}}}"),
+                            candidate.node.byte_range(),
+                            event,
+                        );
+                        None
+                    }
                     Err(e) => {
-                        eprintln!("{e}");
+                        let e = match &e {
+                            // E.g., a sub-pattern that doesn't parse
+                            LuaError::CallbackError { cause, .. } => format!("{cause}\n{e}"),
+                            _ => e.to_string(),
+                        };
+                        // The same code often fails the same way for every
+                        // candidate
+                        if self.lua_errors.borrow_mut().insert(e.clone()) {
+                            eprintln!("{e}");
+                        }
+                        let event = || Event::Lua(code.clone());
+                        trace.record(
+                            || format!("${{{{{code}}}}}"),
+                            candidate.node.byte_range(),
+                            event,
+                        );
                         None
                     }
                 }
@@ -520,15 +1204,43 @@ impl<'nts> Pattern<'nts> {
 
     fn match_node_internal<'tree>(
         &self,
-        lua: &Lua,
+        lua: &LuaState,
+        trace: &Trace,
         env: Env<'tree>,
         goal: Goal,
         candidate: Candidate<'tree>,
     ) -> Option<Match<'tree>> {
         // TODO: Avoid allocation
-        match self.exprs.get(&TmpVar(goal.as_str().to_string())) {
-            None => self.match_plain_node(lua, env, goal, candidate),
-            Some(expr) => self.match_expr(lua, env, expr, candidate),
+        let tvar = TmpVar(goal.as_str().to_string());
+        let range = candidate.node.byte_range();
+        trace.record(|| self.show(goal), range.clone(), || Event::Compare);
+        match self.exprs.get(&tvar) {
+            None => self.match_plain_node(lua, trace, env, goal, candidate),
+            Some(expr) => {
+                if let Some(kind) = self.kinds.get(&tvar) {
+                    if !self
+                        .node_types
+                        .is_descendant_of(candidate.node.kind(), kind)
+                    {
+                        trace.record(|| self.show(goal), range, || Event::NotKind(kind.clone()));
+                        return None;
+                    }
+                }
+                if let Some(regex) = self.regexes.get(&tvar) {
+                    if !regex.as_ref().is_ok_and(|r| r.is_match(candidate.as_str())) {
+                        let event =
+                            || Event::Regex(regex.as_ref().map_or("", |r| r.as_str()).to_string());
+                        trace.record(|| self.show(goal), range, event);
+                        return None;
+                    }
+                }
+                if let Some(alts) = self.alternatives.get(&tvar) {
+                    return alts.iter().find_map(|alt| {
+                        alt.match_node_internal(lua, trace, env.clone(), alt.to_goal(), candidate)
+                    });
+                }
+                self.match_expr(lua, trace, env, expr, candidate)
+            }
         }
     }
 
@@ -540,34 +1252,124 @@ impl<'nts> Pattern<'nts> {
     where
         'tree: 's,
     {
-        let lua = Lua::new();
-        if let Some(m) = self.match_node_internal(&lua, env, self.to_goal(), candidate) {
-            for LuaCode(c) in &self.r#where {
-                let data = LuaData {
-                    env: &m.env,
-                    node_types: self.node_types,
+        self.match_node_with(&LuaState::default(), &Trace::default(), env, candidate)
+    }
+
+    /// Match a single candidate, with fresh Lua globals
+    fn match_node_with<'tree>(
+        &self,
+        lua: &LuaState,
+        trace: &Trace,
+        env: Env<'tree>,
+        candidate: Candidate<'tree>,
+    ) -> Option<Match<'tree>> {
+        lua.fresh(|| self.match_root(lua, trace, env, candidate))
+    }
+
+    fn match_root<'tree>(
+        &self,
+        lua: &LuaState,
+        trace: &Trace,
+        env: Env<'tree>,
+        candidate: Candidate<'tree>,
+    ) -> Option<Match<'tree>> {
+        let goal = self.to_goal();
+        let mut m = self.match_node_internal(lua, trace, env, goal, candidate)?;
+        let range = candidate.node.byte_range();
+        for (relation, pat) in &self.relations {
+            let related = match relation {
+                Relation::Inside | Relation::NotInside => ancestors(m.root),
+                Relation::Has | Relation::NotHas => descendants(m.root),
+            };
+            let found = related.into_iter().find_map(|node| {
+                let candidate = Candidate {
+                    node,
                     text: candidate.text,
                 };
-                match eval_lua::<bool>(&lua, c, &data) {
-                    Ok(b) if b => (),
-                    Ok(_) => return None,
-                    Err(e) => {
-                        eprintln!("Error in Lua: {c}");
-                        eprintln!("{e}");
-                        return None;
-                    }
+                pat.match_at(lua, &Trace::default(), m.env.clone(), candidate)
+            });
+            match (relation, found) {
+                (Relation::Inside | Relation::Has, Some(env)) => m.env = env,
+                (Relation::NotInside | Relation::NotHas, None) => (),
+                _ => {
+                    let event = || Event::Relation(*relation, pat.show(pat.to_goal()));
+                    trace.record(|| self.show(goal), range, event);
+                    return None;
                 }
             }
-            Some(m)
-        } else {
-            None
         }
+        for LuaCode(c) in &self.r#where {
+            let data = LuaData {
+                env: &m.env,
+                node_types: self.node_types,
+                text: candidate.text,
+            };
+            match eval_lua::<bool>(lua, c, &data) {
+                Ok(b) if b => (),
+                Ok(_) => {
+                    trace.record(|| self.show(goal), range, || Event::Where(c.clone()));
+                    return None;
+                }
+                Err(e) => {
+                    eprintln!("Error in Lua: {c}");
+                    eprintln!("{e}");
+                    trace.record(|| self.show(goal), range, || Event::Where(c.clone()));
+                    return None;
+                }
+            }
+        }
+        Some(m)
+    }
+
+    /// Match exactly this node, rather than any of its descendants. Patterns
+    /// of several top-level nodes (e.g., an attribute and the item it applies
+    /// to) match this node and the siblings right before it.
+    fn match_at<'tree>(
+        &self,
+        lua: &LuaState,
+        trace: &Trace,
+        env: Env<'tree>,
+        candidate: Candidate<'tree>,
+    ) -> Option<Env<'tree>> {
+        let goal = self.to_goal();
+        if goal.node.parent().is_none() && goal.node.child_count() > 1 {
+            let goals: Vec<_> = (0..goal.node.child_count())
+                .map(|i| (goal.child(i), None))
+                .collect();
+            let mut candidates = vec![(candidate, None)];
+            while candidates.len() < goals.len() {
+                let node = candidates.last().unwrap().0.node.prev_sibling()?;
+                let sibling = Candidate {
+                    node,
+                    text: candidate.text,
+                };
+                candidates.push((sibling, None));
+            }
+            candidates.reverse();
+            let end = candidate.node.end_byte();
+            return self.match_siblings(lua, trace, env, &goals, &candidates, end, true);
+        }
+        if !self.exprs.contains_key(&TmpVar(goal.as_str().to_string()))
+            && goal.node.kind_id() != candidate.node.kind_id()
+        {
+            trace.record(
+                || self.show(goal),
+                candidate.node.byte_range(),
+                || self.kind(goal, candidate),
+            );
+            return None;
+        }
+        self.match_node_internal(lua, trace, env, goal, candidate)
+            .map(|m| m.env)
     }
 
     // TODO: Only named children
     // TODO: Minimum match size
+    #[allow(clippy::too_many_arguments)]
     fn matches_internal<'tree>(
         &self,
+        lua: &LuaState,
+        trace: &Trace,
         text: &'tree str,
         node: Node<'tree>,
         env: &Env<'tree>,
@@ -582,14 +1384,13 @@ impl<'nts> Pattern<'nts> {
             let mut next = Vec::with_capacity(nodes.len()); // guess
             for node in nodes {
                 let candidate = Candidate { node, text };
-                if let Some(m) = self.match_node(env.clone(), candidate) {
-                    if ranges.contains(&m.root.byte_range()) {
-                        continue;
-                    }
-                    ranges.insert(m.root.byte_range());
-                    ms.push(m);
-                    if limit.map(|l| ms.len() >= l).unwrap_or(false) {
-                        return ms;
+                if let Some(m) = self.match_node_with(lua, trace, env.clone(), candidate) {
+                    // The same node can be found from several of its ancestors
+                    if ranges.insert(m.root.byte_range()) {
+                        ms.push(m);
+                        if limit.map(|l| ms.len() >= l).unwrap_or(false) {
+                            return ms;
+                        }
                     }
                     if !recursive {
                         continue;
@@ -613,27 +1414,56 @@ impl<'nts> Pattern<'nts> {
         recursive: bool,
         limit: Option<usize>,
     ) -> Vec<Match<'tree>> {
-        self.matches_internal(text, tree.root_node(), env, recursive, limit)
+        self.matches_with(&LuaState::default(), tree, text, env, recursive, limit)
     }
 
-    fn to_goal(&self) -> Goal {
-        let mut goal = self.tree.root_node();
-        // See NOTE[expression-hack]
-        while goal.id() != self.root_id {
-            if goal.child_count() == 1 {
-                goal = goal.child(0).unwrap();
-            } else {
-                debug_assert_eq!(goal.named_child_count(), 1);
-                goal = goal.named_child(0).unwrap();
-            }
-        }
+    /// Like [`Pattern::matches`], but with a Lua state that can be reused,
+    /// e.g., for all the patterns and replacements in a file
+    pub(crate) fn matches_with<'tree>(
+        &self,
+        lua: &LuaState,
+        tree: &'tree Tree,
+        text: &'tree str,
+        env: &Env<'tree>,
+        recursive: bool,
+        limit: Option<usize>,
+    ) -> Vec<Match<'tree>> {
+        self.matches_internal(
+            lua,
+            &Trace::default(),
+            text,
+            tree.root_node(),
+            env,
+            recursive,
+            limit,
+        )
+    }
+
+    fn to_goal(&self) -> Goal {
+        // See NOTE[expression-hack] and `ParseContext`: the goal can be
+        // anywhere in the tree, so search for it in pre-order.
+        let mut cursor = self.tree.walk();
+        while cursor.node().id() != self.root_id {
+            if !cursor.goto_first_child() {
+                while !cursor.goto_next_sibling() {
+                    let found_parent = cursor.goto_parent();
+                    debug_assert!(found_parent);
+                }
+            }
+        }
         Goal {
-            node: goal,
+            node: cursor.node(),
             text: &self.text,
         }
     }
 
     pub fn replacement(&self, m: &Match, text: &str) -> String {
+        self.replacement_with(&LuaState::default(), m, text)
+    }
+
+    /// Like [`Pattern::replacement`], but with a Lua state that can be reused,
+    /// see [`Pattern::matches_with`]
+    pub(crate) fn replacement_with(&self, lua: &LuaState, m: &Match, text: &str) -> String {
         // See NOTE[expression-hack] for why this isn't just self.text
         let mut replacement = self
             .to_goal()
@@ -643,15 +1473,27 @@ impl<'nts> Pattern<'nts> {
             .to_string();
 
         for (tvar, expr) in &self.exprs {
+            if self.alternatives.contains_key(tvar) {
+                eprintln!("Alternatives (`${{| ... |}}`) are not valid in replacements");
+                return String::new();
+            }
             match expr {
                 FindExpr::Anonymous => {
                     eprintln!("`$_` is not valid in replacements");
                     return String::new();
                 }
-                FindExpr::Ellipsis => {
+                FindExpr::Ellipsis(None) => {
                     eprintln!("`$..` is not valid in replacements");
                     return String::new();
                 }
+                FindExpr::Ellipsis(Some(mvar @ Metavar(mtxt))) => {
+                    if !m.env.1.contains_key(mvar) {
+                        eprintln!("Bad metavariable in replacement: ..{mtxt}");
+                        return String::new();
+                    }
+                    let siblings = m.env.siblings_range(mvar).map_or("", |r| &text[r]);
+                    replacement = replacement.replace(&tvar.0, siblings);
+                }
                 FindExpr::Metavar(mvar @ Metavar(mtxt)) => match m.env.0.get(mvar) {
                     Some(matching_nodes) => {
                         if let Some(node) = matching_nodes.iter().next() {
@@ -665,13 +1507,13 @@ impl<'nts> Pattern<'nts> {
                     }
                 },
                 FindExpr::Lua(LuaCode(code)) => {
-                    let lua = Lua::new();
                     let data = LuaData {
                         env: &m.env,
                         node_types: self.node_types,
                         text,
                     };
-                    match eval_lua::<String>(&lua, code, &data) {
+                    // Each snippet gets its own globals, like when matching
+                    match lua.fresh(|| eval_lua::<String>(lua, code, &data)) {
                         Ok(evaled) => replacement = replacement.replace(&tvar.0, &evaled),
                         Err(e) => {
                             eprintln!("{e}")
@@ -697,6 +1539,210 @@ impl<'nts> Pattern<'nts> {
     pub fn r#where(&mut self, iter: &mut impl Iterator<Item = LuaCode>) {
         self.r#where.extend(iter);
     }
+
+    /// Require matches to be (or not be) inside, or to (not) contain, a node
+    /// that matches another pattern. Metavariables are shared with it.
+    pub fn relation(&mut self, relation: Relation, pat: Pattern<'nts>) {
+        self.relations.push((relation, pat));
+    }
+
+    /// How the pattern was parsed: The text that was parsed (see
+    /// NOTE[expression-hack]), and the tree of the goal, with what each
+    /// temporary variable stands for
+    pub fn explain(&self) -> String {
+        let goal = self.to_goal().node;
+        let note = |n: Node| {
+            let tvar = TmpVar(n.utf8_text(self.text.as_bytes()).ok()?.to_string());
+            let expr = self.exprs.get(&tvar)?;
+            // Only the outermost node with the variable's text
+            let parent = n.parent().filter(|_| n.id() != goal.id());
+            if parent.is_some_and(|p| p.byte_range() == n.byte_range()) {
+                return None;
+            }
+            Some(self.describe(&tvar, expr))
+        };
+        let mut out = format!("Parsed: {}\nGoal:\n", self.text);
+        out += &dump_with(goal, &self.text, &note);
+        let mut alternatives: Vec<_> = self.alternatives.iter().collect();
+        alternatives.sort_by_key(|(tvar, _)| &tvar.0);
+        for (tvar, alts) in alternatives {
+            for (i, alt) in alts.iter().enumerate() {
+                out += &format!("\nAlternative {} for {}:\n", i + 1, tvar.0);
+                out += &alt.explain();
+            }
+        }
+        out
+    }
+
+    /// The text of part of the pattern, with temporary variables as they were
+    /// written in the pattern
+    fn show(&self, goal: Goal) -> String {
+        let mut text = goal.as_str().to_string();
+        let mut tvars: Vec<_> = self.exprs.iter().collect();
+        // `mogglo_tmp_var_10` before `mogglo_tmp_var_1`
+        tvars.sort_by_key(|(tvar, _)| std::cmp::Reverse(tvar.0.len()));
+        for (tvar, expr) in tvars {
+            if text.contains(&tvar.0) {
+                text = text.replace(&tvar.0, &self.describe(tvar, expr));
+            }
+        }
+        text
+    }
+
+    fn kind(&self, goal: Goal, candidate: Candidate) -> Event {
+        Event::Kind {
+            expected: goal.node.kind().to_string(),
+            found: candidate.node.kind().to_string(),
+        }
+    }
+
+    /// Match a single node, recording the decisions in the trace
+    pub fn trace<'tree>(
+        &self,
+        node: Node<'tree>,
+        text: &'tree str,
+        trace: &Trace,
+    ) -> Option<Match<'tree>> {
+        let candidate = Candidate { node, text };
+        self.match_node_with(&LuaState::default(), trace, Env::default(), candidate)
+    }
+
+    /// A temporary variable, as it was written in the pattern
+    fn describe(&self, tvar: &TmpVar, expr: &FindExpr) -> String {
+        let mut s = match expr {
+            FindExpr::Anonymous if self.alternatives.contains_key(tvar) => "${| ... |}".to_string(),
+            FindExpr::Anonymous if self.regexes.contains_key(tvar) => "$".to_string(),
+            FindExpr::Anonymous => "$_".to_string(),
+            FindExpr::Ellipsis(None) => "$..".to_string(),
+            FindExpr::Ellipsis(Some(m)) => format!("$..{}", m.0),
+            FindExpr::Metavar(m) => format!("${}", m.0),
+            FindExpr::Lua(LuaCode(code)) => format!("${{{{{code}}}}}"),
+        };
+        if let Some(kind) = self.kinds.get(tvar) {
+            s += &format!(":{kind}");
+        }
+        if let Some(regex) = self.regexes.get(tvar) {
+            match regex {
+                Ok(r) => s += &format!("/{}/", r.as_str().replace('/', "\\/")),
+                Err(_) => s += "/(invalid)/",
+            }
+        }
+        s
+    }
+
+    /// Problems with the pattern: Where it failed to parse, and kinds of nodes
+    /// that don't exist (in `$x:kind` or Lua code). Spans are relative to the
+    /// pattern text.
+    pub fn errors(&self) -> &[PatternError] {
+        &self.errors
+    }
+
+    /// In strict mode, the children of a node must match the children of the
+    /// pattern exactly, in order, with no extra siblings. Otherwise, the
+    /// pattern's children may be spread out among other children.
+    pub fn strict(&mut self, strict: bool) {
+        self.strict = strict;
+        for alt in self.alternatives.values_mut().flatten() {
+            alt.strict(strict);
+        }
+        for (_, pat) in &mut self.relations {
+            pat.strict(strict);
+        }
+    }
+}
+
+/// Merge the bindings of a sub-pattern into those of the match, and return
+/// them as a Lua table mapping metavariables to the text they're bound to
+fn export<'lua, 'tree>(
+    lua_ctx: LuaContext<'lua>,
+    binds: &RefCell<Env<'tree>>,
+    sub: Option<Env<'tree>>,
+    text: &str,
+) -> rlua::Result<Option<Table<'lua>>> {
+    let Some(env) = sub else {
+        return Ok(None);
+    };
+    let table = lua_ctx.create_table()?;
+    for (mvar, nodes) in &env.0 {
+        if let Some(node) = nodes.iter().next() {
+            table.set(mvar.0.clone(), node.utf8_text(text.as_bytes()).unwrap())?;
+        }
+    }
+    for mvar in env.1.keys() {
+        let range = env.siblings_range(mvar).unwrap_or_default();
+        table.set(mvar.0.clone(), &text[range])?;
+    }
+    binds.borrow_mut().extend(env);
+    Ok(Some(table))
+}
+
+/// Whether a regex contains something that looks like a metavariable, which
+/// means that it probably wasn't meant to be a regex
+fn has_metavar(regex: &str) -> bool {
+    let mut prev = None;
+    let mut chars = regex.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c == '$' && prev != Some('\\') {
+            if let Some(next) = chars.peek() {
+                if next.is_ascii_alphabetic() || "_.{".contains(*next) {
+                    return true;
+                }
+            }
+        }
+        prev = Some(c);
+    }
+    false
+}
+
+/// The proper ancestors of a node, innermost first
+fn ancestors(node: Node) -> Vec<Node> {
+    let mut ancestors = Vec::new();
+    let mut parent = node.parent();
+    while let Some(p) = parent {
+        ancestors.push(p);
+        parent = p.parent();
+    }
+    ancestors
+}
+
+/// Nodes that failed to parse, outermost only
+fn syntax_errors(node: Node) -> Vec<Node> {
+    if node.is_error() || node.is_missing() {
+        return vec![node];
+    }
+    if !node.has_error() {
+        return Vec::new();
+    }
+    (0..node.child_count())
+        .filter_map(|i| node.child(i))
+        .flat_map(syntax_errors)
+        .collect()
+}
+
+/// The name of the field that holds each child of a node, if any
+fn field_names(node: Node) -> Vec<Option<&'static str>> {
+    let mut fields = Vec::with_capacity(node.child_count());
+    let mut cursor = node.walk();
+    let mut more = cursor.goto_first_child();
+    while more {
+        fields.push(cursor.field_name());
+        more = cursor.goto_next_sibling();
+    }
+    fields
+}
+
+/// The proper descendants of a node, in pre-order
+fn descendants(node: Node) -> Vec<Node> {
+    let mut descendants = Vec::new();
+    let mut stack: Vec<_> = (0..node.child_count())
+        .rev()
+        .filter_map(|i| node.child(i))
+        .collect();
+    while let Some(n) = stack.pop() {
+        descendants.push(n);
+        stack.extend((0..n.child_count()).rev().filter_map(|i| n.child(i)));
+    }
+    descendants
 }
 
 #[cfg(test)]
@@ -708,7 +1754,10 @@ mod tests {
 
     use crate::node_types::NodeTypes;
 
-    use super::{Candidate, Env, FindExpr, LuaCode, Match, Metavar, Pattern};
+    use super::{
+        Candidate, Env, Event, FindExpr, LuaCode, LuaState, Match, Metavar, ParseContext, Pattern,
+        Trace,
+    };
 
     lazy_static::lazy_static! {
         /// This is an example for using doc comment attributes
@@ -811,6 +1860,46 @@ mod tests {
             HashMap::from([(Pattern::meta(0), FindExpr::Anonymous)]),
             pat("$_").exprs
         );
+        assert_eq!(
+            HashMap::from([(Pattern::meta(0), FindExpr::Ellipsis(None))]),
+            pat("$..").exprs
+        );
+        let p = pat("$x:identifier + $_:_expression");
+        assert_eq!(
+            HashMap::from([
+                (
+                    Pattern::meta(0),
+                    FindExpr::Metavar(Metavar("x".to_string()))
+                ),
+                (Pattern::meta(1), FindExpr::Anonymous)
+            ]),
+            p.exprs
+        );
+        assert_eq!(
+            HashMap::from([
+                (Pattern::meta(0), "identifier".to_string()),
+                (Pattern::meta(1), "_expression".to_string())
+            ]),
+            p.kinds
+        );
+        assert_eq!(HashMap::new(), pat("$x::new()").kinds);
+        let p = pat(r"$x/^a\/b$/ + $/c/ / $y");
+        let regexes: HashMap<_, _> = p
+            .regexes
+            .iter()
+            .map(|(k, v)| (k.clone(), v.as_ref().unwrap().as_str()))
+            .collect();
+        assert_eq!(
+            HashMap::from([(Pattern::meta(0), "^a/b$"), (Pattern::meta(1), "c")]),
+            regexes
+        );
+        assert_eq!(
+            HashMap::from([(
+                Pattern::meta(0),
+                FindExpr::Ellipsis(Some(Metavar("args".to_string())))
+            )]),
+            pat("$..args").exprs
+        );
         assert_eq!(
             HashMap::from([(Pattern::meta(0), FindExpr::Lua(LuaCode("true".to_string())))]),
             pat("${{true}}").exprs
@@ -897,11 +1986,38 @@ mod tests {
             matches("$x + $y", &tree, text)
         );
 
-        // TODO:
-        // let text = "let a = a;";
-        // let tree = super::parse(language(), text);
-        // assert_eq!(Some(HashMap::new()), matches("$/a/", &tree, text));
-        // assert_eq!(Some(HashMap::new()), matches("$/./", &tree, text));
+        let text = "a.unwrap();";
+        let tree = super::parse(language(), text);
+        assert_eq!(Some(HashMap::new()), matches("$_.unwrap()", &tree, text));
+
+        let text = "let a = a;";
+        let tree = super::parse(language(), text);
+        assert_eq!(Some(HashMap::new()), matches("$/a/", &tree, text));
+        assert_eq!(Some(HashMap::new()), matches("$/./", &tree, text));
+        assert_eq!(None, matches("$/b/", &tree, text));
+
+        let text = "let unsafe_a = b;";
+        let tree = super::parse(language(), text);
+        assert_eq!(
+            Some(HashMap::from([(
+                Metavar("x".to_string()),
+                HashSet::from(["unsafe_a"])
+            )])),
+            matches("let $x/^unsafe_/ = $_;", &tree, text)
+        );
+        assert_eq!(None, matches("let $x/^safe_/ = $_;", &tree, text));
+        assert_eq!(None, matches(r"let $/\// = $_;", &tree, text));
+
+        let text = "a/2 + b/3;";
+        let tree = super::parse(language(), text);
+        assert_eq!(
+            Some(HashMap::from([
+                (Metavar("x".to_string()), HashSet::from(["a"])),
+                (Metavar("y".to_string()), HashSet::from(["b"]))
+            ])),
+            matches("$x/2 + $y/3", &tree, text)
+        );
+        assert!(matches("$x/$y", &tree, text).is_none());
 
         // TODO:
         // let text = "let foo = 0 == 1;";
@@ -972,10 +2088,286 @@ mod tests {
         assert_eq!(
             Some(HashMap::from([(
                 Metavar("x".to_string()),
-                HashSet::from(["b"])
+                HashSet::from(["a"])
             ),])),
             matches("{ $..; $x; $.. }", &tree, text)
         );
+
+        // Before the first ellipsis, candidates are skipped like they are
+        // without ellipses, so `$..` matches wherever another statement would
+        let text = "{ let a = 0; b; c; }";
+        let tree = super::parse(language(), text);
+        assert_eq!(
+            Some(HashMap::from([(
+                Metavar("x".to_string()),
+                HashSet::from(["b"])
+            ),])),
+            matches("{ $x; $.. }", &tree, text)
+        );
+        assert_eq!(
+            Some(HashMap::from([
+                (Metavar("x".to_string()), HashSet::from(["b"])),
+                (Metavar("y".to_string()), HashSet::from(["c"]))
+            ])),
+            matches("{ $x; $y; }", &tree, text)
+        );
+
+        let text = "f(a, b, c)";
+        let tree = super::parse(language(), text);
+        assert_eq!(
+            Some(HashMap::from([(
+                Metavar("x".to_string()),
+                HashSet::from(["c"])
+            ),])),
+            matches("f($.., $x)", &tree, text)
+        );
+
+        let text = "fn f() { a; return b; }";
+        let tree = super::parse(language(), text);
+        assert_eq!(
+            Some(HashMap::from([(
+                Metavar("x".to_string()),
+                HashSet::from(["b"])
+            ),])),
+            matches("{ $..; return $x; }", &tree, text)
+        );
+
+        let text = "fn f() { return b; a; }";
+        let tree = super::parse(language(), text);
+        assert_eq!(None, matches("{ $..; return $x; }", &tree, text));
+
+        let text = "f(a, b, c)";
+        let tree = super::parse(language(), text);
+        let env = match_one("f($..xs)", &tree, text).unwrap();
+        assert_eq!(Some(2..9), env.siblings_range(&Metavar("xs".to_string())));
+        let env = match_one("f($x, $..xs)", &tree, text).unwrap();
+        assert_eq!(Some(5..9), env.siblings_range(&Metavar("xs".to_string())));
+
+        let text = "f()";
+        let tree = super::parse(language(), text);
+        let env = match_one("f($..xs)", &tree, text).unwrap();
+        assert_eq!(None, env.siblings_range(&Metavar("xs".to_string())));
+
+        let text = "fn h() { f(a, b + c); g(a, b+c); g(a, c); }";
+        let tree = super::parse(language(), text);
+        assert!(matches("{ f($..xs); g($..xs); $.. }", &tree, text).is_some());
+        assert!(matches("{ f($..xs); $..; g($..xs); }", &tree, text).is_none());
+        assert!(matches("{ f($..xs); g($x, $..xs); $.. }", &tree, text).is_none());
+    }
+
+    #[test]
+    fn test_kinds() {
+        let text = "let a = b;";
+        let tree = super::parse(language(), text);
+        assert!(matches("let $x:identifier = $y;", &tree, text).is_some());
+        assert!(matches("let $x = $y:_expression;", &tree, text).is_some());
+        assert!(matches("let $x:integer_literal = $y;", &tree, text).is_none());
+
+        let text = "let (a, b) = c;";
+        let tree = super::parse(language(), text);
+        assert!(matches("let $x:identifier = $y;", &tree, text).is_none());
+        assert!(matches("let $x:_pattern = $y;", &tree, text).is_some());
+    }
+
+    #[test]
+    fn test_alternatives() {
+        let p = pat("f(${| a $| ${| b $| c |} |})");
+        assert_eq!(2, p.alternatives[&Pattern::meta(0)].len());
+        let text = "f(c)";
+        let tree = super::parse(language(), text);
+        assert!(matches("f(${| a $| ${| b $| c |} |})", &tree, text).is_some());
+        assert!(matches("f(${| a $| b |})", &tree, text).is_none());
+
+        let text = "let a = a.unwrap();";
+        let tree = super::parse(language(), text);
+        assert_eq!(
+            Some(HashMap::from([(
+                Metavar("x".to_string()),
+                HashSet::from(["a"])
+            )])),
+            matches("let $x = ${| $x.unwrap() $| $x.expect($_) |};", &tree, text)
+        );
+        let text = "let a = a.expect(\"a\");";
+        let tree = super::parse(language(), text);
+        assert!(matches("let $x = ${| $x.unwrap() $| $x.expect($_) |};", &tree, text).is_some());
+        let text = "let a = b.unwrap();";
+        let tree = super::parse(language(), text);
+        assert!(matches("let $x = ${| $x.unwrap() $| $x.expect($_) |};", &tree, text).is_none());
+
+        let text = "a.unwrap(); b.expect(c); d.unwrap_or(e);";
+        let tree = super::parse(language(), text);
+        let p = Pattern::alternatives(
+            language(),
+            &NODE_TYPES,
+            vec!["$x.unwrap()".to_string(), "$x.expect($_)".to_string()],
+            ParseContext::default(),
+        );
+        assert_eq!(2, p.matches(&tree, text, &Env::default(), true, None).len());
+    }
+
+    #[test]
+    fn test_strict() {
+        let strict = |s: &str, text: &str| {
+            let tree = super::parse(language(), text);
+            let mut p = pat(s);
+            p.strict(true);
+            p.matches(&tree, text, &Env::default(), false, None).len()
+        };
+        assert_eq!(1, strict("{ $f($x); $y + $z; }", "{ f(a); b + c; }"));
+        assert_eq!(0, strict("{ $f($x); $y + $z; }", "{ f(a); g(); b + c; }"));
+        assert_eq!(0, strict("{ $f($x); $y + $z; }", "{ f(a); b + c; d; }"));
+        assert_eq!(1, strict("f($x)", "f(a)"));
+        assert_eq!(0, strict("f($x)", "f(a, b)"));
+        assert_eq!(1, strict("f($x, $..)", "f(a, b)"));
+        assert_eq!(
+            1,
+            strict("{ $f($x); $y + $z; }", "{ f(a); /* c */ b + c; }")
+        );
+        assert_eq!(
+            1,
+            strict("{ $f($x); /* c */ $y + $z; }", "{ f(a); b + c; }")
+        );
+        assert_eq!(
+            1,
+            strict(
+                "fn $f() { $..; return $x; }",
+                "fn f() { a; return b; // c\n}"
+            )
+        );
+    }
+
+    #[test]
+    fn test_errors() {
+        fn errors(s: &str) -> Vec<(&str, bool)> {
+            pat(s)
+                .errors()
+                .iter()
+                .map(|e| (&s[e.span.clone()], e.syntax))
+                .collect()
+        }
+        assert!(errors("let $x = $y;").is_empty());
+        assert!(errors("$x + $y").is_empty());
+        assert_eq!(vec![("identfier", false)], errors("$x:identfier + $y"));
+        assert_eq!(
+            Some("Did you mean `identifier`?".to_string()),
+            pat("$x:identfier").errors()[0].help
+        );
+        assert_eq!(
+            vec![("identfier", false)],
+            errors(r#"${{is_child_of("identfier", "_expression")}}"#)
+        );
+        assert_eq!(
+            vec![("identfier", false)],
+            errors(r#"let $y = ${| $x:identfier $| 1 |};"#)
+        );
+        assert_eq!(
+            vec![("expresion_statement", false)],
+            errors(r#"${{can_contain(focus:parent():kind(), "expresion_statement")}}"#)
+        );
+        assert_eq!(vec![("$x/(/", false)], errors("$x/(/ + $y"));
+        assert!(errors("$x/2 + $y/3").is_empty());
+        assert_eq!(vec![("$x/a + $y/", false)], errors("f($x/a + $y/)"));
+        assert!(errors(r"$x/^\$a/ + $y").is_empty());
+        let errs = errors("let $x = $y = ;");
+        assert!(!errs.is_empty());
+        assert!(errs.iter().all(|(_, syntax)| *syntax));
+    }
+
+    #[test]
+    fn test_context() {
+        let in_ctx = |s: &str, template: &str, kind: Option<&str>| {
+            let ctx = ParseContext {
+                template: Some(template),
+                kind,
+            };
+            Pattern::parse_in(language(), &NODE_TYPES, s.to_string(), ctx)
+        };
+        let count = |p: &Pattern, text: &str| {
+            let tree = super::parse(language(), text);
+            p.matches(&tree, text, &Env::default(), true, None).len()
+        };
+        let text = "match x { Some(a) => a, None => b }";
+
+        let p = in_ctx("Some($x) => $y,", "match z { $PAT }", None);
+        assert_eq!("match_arm", p.to_goal().node.kind());
+        assert_eq!(1, count(&p, text));
+        let p = in_ctx("$p => $y", "match z { $PAT }", Some("match_arm"));
+        assert_eq!("match_arm", p.to_goal().node.kind());
+        assert_eq!(2, count(&p, text));
+
+        let p = in_ctx("$f: $t", "struct S { $PAT }", Some("field_declaration"));
+        assert_eq!(1, count(&p, "struct T { a: A }"));
+
+        let p = Pattern::parse_kind(
+            language(),
+            &NODE_TYPES,
+            "$x;".to_string(),
+            "expression_statement",
+        );
+        assert_eq!("expression_statement", p.to_goal().node.kind());
+    }
+
+    #[test]
+    fn test_explain() {
+        let p = Pattern::parse(language(), &NODE_TYPES, "$x + $..y".to_string());
+        assert_eq!(
+            "Parsed: { mogglo_tmp_var_0 + mogglo_tmp_var_1 }\n\
+             Goal:\n\
+             binary_expression 2..37\n  \
+               left: identifier 2..18 \"mogglo_tmp_var_0\"  # $x\n  \
+               operator: \"+\" 19..20\n  \
+               right: identifier 21..37 \"mogglo_tmp_var_1\"  # $..y\n",
+            p.explain()
+        );
+    }
+
+    #[test]
+    fn test_trace() {
+        let trace = |s: &str, text: &str| {
+            let tree = super::parse(language(), text);
+            let node = tree.root_node().child(0).unwrap();
+            let trace = Trace::new();
+            assert!(pat(s).trace(node, text, &trace).is_none());
+            let step = trace.deepest_failure().unwrap();
+            (step.goal, text[step.candidate].to_string(), step.event)
+        };
+        assert_eq!(
+            ("3".to_string(), "2".to_string(), Event::Text),
+            trace("f($x, 3);", "f(1, 2);")
+        );
+        let (goal, candidate, event) = trace("f($x, $x);", "f(1, 2);");
+        assert_eq!(("$x", "2"), (goal.as_str(), candidate.as_str()));
+        assert!(matches!(event, Event::Metavar { name, bound } if name == "x" && bound == (2..3)));
+        assert_eq!(
+            (
+                "$x:identifier".to_string(),
+                "1".to_string(),
+                Event::NotKind("identifier".to_string())
+            ),
+            trace("f($x:identifier);", "f(1);")
+        );
+    }
+
+    #[test]
+    fn test_fields() {
+        // `mut` isn't in the `pattern` field
+        let text = "let mut a = b;";
+        let tree = super::parse(language(), text);
+        assert_eq!(
+            Some(HashMap::from([
+                (Metavar("x".to_string()), HashSet::from(["a"])),
+                (Metavar("y".to_string()), HashSet::from(["b"]))
+            ])),
+            matches("let $x = $y;", &tree, text)
+        );
+
+        let text = "fn f(a: A) -> B { c }";
+        let tree = super::parse(language(), text);
+        assert!(matches("fn $f($..) -> $t { $.. }", &tree, text).is_some());
+        let text = "fn f(a: A) { c }";
+        let tree = super::parse(language(), text);
+        assert!(matches("fn $f($..) -> $t { $.. }", &tree, text).is_none());
+        assert!(matches("fn $f($..) { $.. }", &tree, text).is_some());
     }
 
     #[test]
@@ -991,6 +2383,108 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_lua_globals() {
+        // Snippets share globals when matching a single candidate...
+        let text = "let a = b;";
+        let tree = super::parse(language(), text);
+        assert!(matches(
+            "let ${{foo = 1; return true}} = ${{foo == 1}};",
+            &tree,
+            text
+        )
+        .is_some());
+
+        // ...but not with snippets in sub-patterns
+        assert_eq!(
+            Some(HashMap::from([
+                (Metavar("y".to_string()), HashSet::from(["a"])),
+                (Metavar("x".to_string()), HashSet::from(["b"]))
+            ])),
+            matches(
+                r#"let $y = ${{local m = match("${{t == \"b\"}}"); bind("x"); return m and focus:text() == "b"}};"#,
+                &tree,
+                text
+            )
+        );
+        assert!(matches(
+            r#"let $y = ${{local p = pat("${{focus = nil; return true}}"); return pmatch(p, focus) and focus:text() == "b"}};"#,
+            &tree,
+            text
+        )
+        .is_some());
+
+        // ...but not across candidates
+        let text = "let a = 1; let b = 2;";
+        let tree = super::parse(language(), text);
+        assert_eq!(
+            2,
+            pat("let $x = ${{n = (n or 0) + 1; return n == 1}};")
+                .matches(&tree, text, &Env::default(), true, None)
+                .len()
+        );
+
+        // ...nor between matching and replacements in the same Lua state
+        let lua = LuaState::default();
+        let ms = pat("let $x = ${{n = (n or 0) + 1; return true}};").matches_with(
+            &lua,
+            &tree,
+            text,
+            &Env::default(),
+            true,
+            None,
+        );
+        let replace = pat("let $x = ${{n = (n or 0) + 1; return tostring(n)}};");
+        let replaced: Vec<_> = ms
+            .iter()
+            .map(|m| replace.replacement_with(&lua, m, text))
+            .collect();
+        assert_eq!(vec!["let a = 1;", "let b = 1;"], replaced);
+    }
+
+    #[test]
+    fn test_export() {
+        let text = "let y = g(b);";
+        let tree = super::parse(language(), text);
+        assert_eq!(
+            Some(HashMap::from([
+                (Metavar("x".to_string()), HashSet::from(["y"])),
+                (Metavar("f".to_string()), HashSet::from(["g"])),
+                (Metavar("a".to_string()), HashSet::from(["b"]))
+            ])),
+            matches(r#"let $x = ${{match_export("$f($a)")}};"#, &tree, text)
+        );
+        assert_eq!(
+            Some(HashMap::from([(
+                Metavar("x".to_string()),
+                HashSet::from(["y"])
+            )])),
+            matches(r#"let $x = ${{match("$f($a)")}};"#, &tree, text)
+        );
+        assert!(matches(
+            r#"let $x = ${{local b = match_export("$f($a)"); return b.a == "b"}};"#,
+            &tree,
+            text
+        )
+        .is_some());
+        assert!(matches(r#"let $x = ${{match_export("$f($x)")}};"#, &tree, text).is_none());
+        // Sub-patterns that don't parse are errors in the Lua code
+        assert!(matches(
+            r#"let $x = ${{local ok, e = pcall(match_export, "$f("); return not ok and tostring(e):find("sub%-pattern") ~= nil}};"#,
+            &tree,
+            text
+        )
+        .is_some());
+        assert_eq!(
+            "let y = b;",
+            replace(
+                "let y = g(b).h();",
+                r#"let $x = ${{rec_export("$f($a)")}};"#,
+                "let $x = $a;"
+            )
+        );
+    }
+
     #[test]
     fn test_replace() {
         assert_eq!("a", replace("let a = b;", "let $x = $y;", "$x"));
@@ -999,5 +2493,13 @@ mod tests {
             replace("let a = b;", "let $x = $y;", "let $y = $x;")
         );
         assert_eq!("", replace("let a = b;", "let $x = $y;", r#"${{""}}"#));
+        assert_eq!(
+            "bar(ctx, a, b);",
+            replace("foo(a, b);", "foo($..args)", "bar(ctx, $..args)")
+        );
+        assert_eq!(
+            "f(b, a);",
+            replace("f(a, b);", "f($x, $..rest)", "f($..rest, $x)")
+        );
     }
 }
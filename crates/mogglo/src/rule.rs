@@ -0,0 +1,136 @@
+use std::fmt;
+
+use tree_sitter::Language;
+
+use crate::{
+    node_types::NodeTypes,
+    pattern::{LuaCode, ParseContext, Pattern, Relation},
+};
+
+#[cfg(feature = "rules")]
+mod load;
+
+#[cfg(feature = "rules")]
+pub use load::Error;
+
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq, serde::Deserialize, serde::Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    Error,
+    #[default]
+    Warning,
+    Info,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Severity::Error => write!(f, "error"),
+            Severity::Warning => write!(f, "warning"),
+            Severity::Info => write!(f, "info"),
+        }
+    }
+}
+
+/// A pattern bundled with its where-clauses, replacement, and message
+#[derive(Clone, Debug, Eq, PartialEq, serde::Deserialize, serde::Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct Rule {
+    pub id: String,
+    pub language: String,
+    pub pattern: String,
+    /// Other patterns, any of which can match instead of `pattern`
+    #[serde(default)]
+    pub or: Vec<String>,
+    /// Code to parse the pattern and replacement in, see [`ParseContext`]
+    #[serde(default)]
+    pub context: Option<String>,
+    /// Kind of node to use as the pattern, see [`ParseContext`]
+    #[serde(default)]
+    pub kind: Option<String>,
+    #[serde(default)]
+    pub r#where: Vec<String>,
+    /// Patterns that some ancestor of each match must match
+    #[serde(default)]
+    pub inside: Vec<String>,
+    /// Patterns that no ancestor of a match may match
+    #[serde(default)]
+    pub not_inside: Vec<String>,
+    /// Patterns that some descendant of each match must match
+    #[serde(default)]
+    pub has: Vec<String>,
+    /// Patterns that no descendant of a match may match
+    #[serde(default)]
+    pub not_has: Vec<String>,
+    #[serde(default)]
+    pub replace: Option<String>,
+    /// Match children exactly, see [`Pattern::strict`]
+    #[serde(default)]
+    pub strict: bool,
+    #[serde(default)]
+    pub severity: Severity,
+    pub message: String,
+    /// Code that the rule should not match
+    #[serde(default)]
+    pub valid: Vec<String>,
+    /// Code that the rule should match
+    #[serde(default)]
+    pub invalid: Vec<Example>,
+}
+
+/// Code that a rule should match, and optionally the result of applying the
+/// rule's replacement to it
+#[derive(Clone, Debug, Eq, PartialEq, serde::Deserialize, serde::Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct Example {
+    pub code: String,
+    #[serde(default)]
+    pub fixed: Option<String>,
+}
+
+impl Rule {
+    /// How to parse the pattern and replacement
+    pub fn parse_context(&self) -> ParseContext<'_> {
+        ParseContext {
+            template: self.context.as_deref(),
+            kind: self.kind.as_deref(),
+        }
+    }
+
+    /// The rule's pattern, including its where-clauses
+    pub fn pattern<'nts>(
+        &self,
+        lang: Language,
+        node_types: &'nts NodeTypes<'nts>,
+    ) -> Pattern<'nts> {
+        let mut pat = if self.or.is_empty() {
+            Pattern::parse_in(lang, node_types, self.pattern.clone(), self.parse_context())
+        } else {
+            let pats = std::iter::once(&self.pattern).chain(&self.or).cloned();
+            Pattern::alternatives(lang, node_types, pats.collect(), self.parse_context())
+        };
+        pat.r#where(&mut self.r#where.iter().cloned().map(LuaCode));
+        for (relation, pats) in [
+            (Relation::Inside, &self.inside),
+            (Relation::NotInside, &self.not_inside),
+            (Relation::Has, &self.has),
+            (Relation::NotHas, &self.not_has),
+        ] {
+            for p in pats {
+                pat.relation(relation, Pattern::parse(lang, node_types, p.clone()));
+            }
+        }
+        pat.strict(self.strict);
+        pat
+    }
+
+    pub fn replacement<'nts>(
+        &self,
+        lang: Language,
+        node_types: &'nts NodeTypes<'nts>,
+    ) -> Option<Pattern<'nts>> {
+        self.replace
+            .as_ref()
+            .map(|r| Pattern::parse_in(lang, node_types, r.clone(), self.parse_context()))
+    }
+}
@@ -0,0 +1,206 @@
+use std::{
+    fmt, fs, io,
+    path::{Path, PathBuf},
+};
+
+use super::Rule;
+
+/// Rule files contain a list of rules
+#[derive(Debug, serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RuleFile {
+    rules: Vec<Rule>,
+}
+
+#[derive(Debug)]
+pub enum Error {
+    Io(PathBuf, io::Error),
+    Toml(PathBuf, toml::de::Error),
+    Yaml(PathBuf, serde_yaml::Error),
+    Extension(PathBuf),
+    /// A rule is for a language that isn't one of the given ones
+    Language(PathBuf, String, Vec<String>),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Io(p, e) => write!(f, "Failed to read {}: {e}", p.display()),
+            Error::Toml(p, e) => write!(f, "Bad rule file {}: {e}", p.display()),
+            Error::Yaml(p, e) => write!(f, "Bad rule file {}: {e}", p.display()),
+            Error::Extension(p) => write!(
+                f,
+                "Rule file {} should end in .toml, .yaml, or .yml",
+                p.display()
+            ),
+            Error::Language(p, l, expected) => write!(
+                f,
+                "Unknown language {l} in rule file {}, expected one of: {}",
+                p.display(),
+                expected.join(", ")
+            ),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+const EXTENSIONS: &[&str] = &["toml", "yaml", "yml"];
+
+impl Rule {
+    pub fn from_toml(s: &str) -> Result<Vec<Self>, toml::de::Error> {
+        toml::from_str::<RuleFile>(s).map(|f| f.rules)
+    }
+
+    pub fn from_yaml(s: &str) -> Result<Vec<Self>, serde_yaml::Error> {
+        serde_yaml::from_str::<RuleFile>(s).map(|f| f.rules)
+    }
+
+    fn load_file(path: &Path, languages: &[&str]) -> Result<Vec<Self>, Error> {
+        let s = fs::read_to_string(path).map_err(|e| Error::Io(path.to_path_buf(), e))?;
+        let rules = match path.extension().and_then(|e| e.to_str()) {
+            Some("toml") => Self::from_toml(&s).map_err(|e| Error::Toml(path.to_path_buf(), e)),
+            Some("yaml" | "yml") => {
+                Self::from_yaml(&s).map_err(|e| Error::Yaml(path.to_path_buf(), e))
+            }
+            _ => Err(Error::Extension(path.to_path_buf())),
+        }?;
+        if let Some(r) = rules
+            .iter()
+            .find(|r| !languages.contains(&r.language.as_str()))
+        {
+            let expected = languages.iter().map(|l| l.to_string()).collect();
+            return Err(Error::Language(
+                path.to_path_buf(),
+                r.language.clone(),
+                expected,
+            ));
+        }
+        Ok(rules)
+    }
+
+    /// Load rules from a file, or from all the rule files in a directory. It's
+    /// an error for a rule to be for a language that isn't one of `languages`.
+    pub fn load(path: &Path, languages: &[&str]) -> Result<Vec<Self>, Error> {
+        if !path.is_dir() {
+            return Self::load_file(path, languages);
+        }
+        let mut paths = Vec::new();
+        for entry in fs::read_dir(path).map_err(|e| Error::Io(path.to_path_buf(), e))? {
+            let p = entry.map_err(|e| Error::Io(path.to_path_buf(), e))?.path();
+            if p.extension()
+                .and_then(|e| e.to_str())
+                .is_some_and(|e| EXTENSIONS.contains(&e))
+            {
+                paths.push(p);
+            }
+        }
+        paths.sort();
+        let mut rules = Vec::new();
+        for p in paths {
+            rules.extend(Self::load_file(&p, languages)?);
+        }
+        Ok(rules)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::rule::{Example, Rule, Severity};
+
+    #[test]
+    fn test_parse() {
+        let toml = r#"
+[[rules]]
+id = "self-assign"
+language = "rust"
+pattern = "let $x = $x;"
+or = ["$x = $x;"]
+not_inside = ["fn $f() { $.. }"]
+message = "Pointless assignment"
+
+[[rules]]
+id = "unit"
+language = "rust"
+pattern = "let $x = $y;"
+where = ["y == \"()\""]
+replace = "let $x = ();"
+strict = true
+severity = "info"
+message = "Unit"
+valid = ["let a = b;"]
+
+[[rules.invalid]]
+code = "let a = ();"
+fixed = "let a = ();"
+"#;
+        let yaml = r#"
+rules:
+  - id: self-assign
+    language: rust
+    pattern: let $x = $x;
+    or:
+      - $x = $x;
+    not_inside:
+      - fn $f() { $.. }
+    message: Pointless assignment
+  - id: unit
+    language: rust
+    pattern: let $x = $y;
+    where:
+      - y == "()"
+    replace: let $x = ();
+    strict: true
+    severity: info
+    message: Unit
+    valid:
+      - let a = b;
+    invalid:
+      - code: let a = ();
+        fixed: let a = ();
+"#;
+        let rules = Rule::from_toml(toml).unwrap();
+        assert_eq!(rules, Rule::from_yaml(yaml).unwrap());
+        assert_eq!(2, rules.len());
+        assert_eq!(Severity::Warning, rules[0].severity);
+        assert_eq!(None, rules[0].replace);
+        assert_eq!(vec!["$x = $x;".to_string()], rules[0].or);
+        assert!(rules[1].or.is_empty());
+        assert_eq!(vec!["fn $f() { $.. }".to_string()], rules[0].not_inside);
+        assert!(rules[0].inside.is_empty());
+        assert!(!rules[0].strict);
+        assert!(rules[1].strict);
+        assert_eq!(Severity::Info, rules[1].severity);
+        assert_eq!(vec![r#"y == "()""#.to_string()], rules[1].r#where);
+        assert_eq!(
+            vec![Example {
+                code: "let a = ();".to_string(),
+                fixed: Some("let a = ();".to_string())
+            }],
+            rules[1].invalid
+        );
+    }
+
+    #[test]
+    fn test_unknown_field() {
+        let toml = r#"
+[[rules]]
+id = "unit"
+language = "rust"
+pattern = "let $x = $y;"
+replacement = "let $x = ();"
+message = "Unit"
+"#;
+        assert!(Rule::from_toml(toml).is_err());
+        let yaml = r#"
+rules:
+  - id: unit
+    language: rust
+    pattern: let $x = $y;
+    not-inside:
+      - fn $f() { $.. }
+    message: Unit
+"#;
+        assert!(Rule::from_yaml(yaml).is_err());
+    }
+}
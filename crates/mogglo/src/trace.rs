@@ -0,0 +1,165 @@
+use std::{
+    cell::{Cell, RefCell},
+    fmt,
+    ops::Range,
+};
+
+use crate::pattern::Relation;
+
+/// Something that happened while matching a part of a pattern against a node
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Event {
+    /// The part of the pattern was compared with the node
+    Compare,
+    /// The node is of a different kind
+    Kind { expected: String, found: String },
+    /// The node is a leaf with different text
+    Text,
+    /// The node has children, but the part of the pattern doesn't
+    Children,
+    /// There was no node left in the field, or no node left at all
+    Missing { field: Option<String> },
+    /// There were nodes left over after matching all of the siblings
+    Extra,
+    /// The node isn't of the kind given in `$x:kind`
+    NotKind(String),
+    /// The text of the node doesn't match the regex in `$x/regex/`
+    Regex(String),
+    /// The node isn't equal to an earlier occurrence of the metavariable
+    Metavar { name: String, bound: Range<usize> },
+    /// A Lua snippet in the pattern returned false, or failed
+    Lua(String),
+    /// A `--where` clause returned false, or failed
+    Where(String),
+    /// A relation to another pattern (e.g., `--inside`) didn't hold
+    Relation(Relation, String),
+}
+
+impl Event {
+    pub fn is_failure(&self) -> bool {
+        !matches!(self, Event::Compare)
+    }
+}
+
+impl fmt::Display for Event {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Event::Compare => write!(f, "Compared"),
+            Event::Kind { expected, found } => {
+                write!(f, "Expected a node of kind {expected}, found {found}")
+            }
+            Event::Text => write!(f, "Different text"),
+            Event::Children => write!(f, "Expected a leaf, found a node with children"),
+            Event::Missing { field: Some(field) } => {
+                write!(f, "No match in the remaining children in field {field}")
+            }
+            Event::Missing { field: None } => write!(f, "No match in the remaining children"),
+            Event::Extra => write!(f, "Extra children"),
+            Event::NotKind(kind) => write!(f, "Not a kind of {kind}"),
+            Event::Regex(regex) => write!(f, "Doesn't match the regex /{regex}/"),
+            Event::Metavar { name, .. } => {
+                write!(f, "Not equal to the earlier occurrence of ${name}")
+            }
+            Event::Lua(code) => write!(f, "Lua code was not true: {code}"),
+            Event::Where(code) => write!(f, "Where clause was not true: {code}"),
+            Event::Relation(relation, pat) => {
+                let relation = match relation {
+                    Relation::Inside => "inside",
+                    Relation::NotInside => "not-inside",
+                    Relation::Has => "has",
+                    Relation::NotHas => "not-has",
+                };
+                write!(f, "Relation {relation} didn't hold: {pat}")
+            }
+        }
+    }
+}
+
+/// A matching decision, see [`Trace`]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Step {
+    /// How many levels of the pattern deep the step is
+    pub depth: usize,
+    /// The part of the pattern, with metavariables as they were written
+    pub goal: String,
+    /// The node it was matched against
+    pub candidate: Range<usize>,
+    pub event: Event,
+}
+
+/// A record of the decisions made while matching, see [`Pattern::trace`]. The
+/// default trace records nothing.
+///
+/// [`Pattern::trace`]: crate::pattern::Pattern::trace
+#[derive(Debug, Default)]
+pub struct Trace {
+    steps: Option<RefCell<Vec<Step>>>,
+    depth: Cell<usize>,
+}
+
+impl Trace {
+    /// A trace that records steps
+    pub fn new() -> Self {
+        Self {
+            steps: Some(RefCell::new(Vec::new())),
+            depth: Cell::new(0),
+        }
+    }
+
+    /// Record a step. The goal and event are only built when recording, as
+    /// this is called while matching even if nothing is traced.
+    pub(crate) fn record(
+        &self,
+        goal: impl FnOnce() -> String,
+        candidate: Range<usize>,
+        event: impl FnOnce() -> Event,
+    ) {
+        if let Some(steps) = &self.steps {
+            steps.borrow_mut().push(Step {
+                depth: self.depth.get(),
+                goal: goal(),
+                candidate,
+                event: event(),
+            });
+        }
+    }
+
+    /// Run a function one level deeper in the pattern
+    pub(crate) fn nested<T>(&self, f: impl FnOnce() -> T) -> T {
+        self.depth.set(self.depth.get() + 1);
+        let result = f();
+        self.depth.set(self.depth.get() - 1);
+        result
+    }
+
+    pub fn steps(&self) -> Vec<Step> {
+        self.steps
+            .as_ref()
+            .map(|s| s.borrow().clone())
+            .unwrap_or_default()
+    }
+
+    /// The first of the failures that are deepest in the pattern. Failed
+    /// `--where` clauses and relations come after the whole pattern matched,
+    /// so they take precedence over failures that matching recovered from.
+    pub fn deepest_failure(&self) -> Option<Step> {
+        let steps = self.steps();
+        if let Some(step) = steps
+            .iter()
+            .find(|s| matches!(s.event, Event::Where(_) | Event::Relation(..)))
+        {
+            return Some(step.clone());
+        }
+        let mut deepest: Option<Step> = None;
+        for step in steps {
+            if !step.event.is_failure() {
+                continue;
+            }
+            match &deepest {
+                Some(d) if d.depth >= step.depth => (),
+                _ => deepest = Some(step),
+            }
+        }
+        deepest
+    }
+}
@@ -0,0 +1,12 @@
+// RUN: mogglo-rust --only-matching '$x.unwrap()' --or '$x.expect($_)' %s | FileCheck %s
+// RUN: mogglo-rust --only-matching 'let $x = ${| $y.unwrap() $| $y.expect($_) |};' %s | FileCheck --check-prefix=INLINE %s
+
+// CHECK: a.unwrap()
+// CHECK-NEXT: b.expect("b")
+// CHECK-NOT: {{.+}}
+// INLINE: let a = a.unwrap();
+// INLINE-NEXT: let b = b.expect("b");
+// INLINE-NOT: {{.+}}
+let a = a.unwrap();
+let b = b.expect("b");
+let c = c.unwrap_or(d);
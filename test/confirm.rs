@@ -0,0 +1,6 @@
+// RUN: rm -rf %t && mkdir -p %t && echo 'f(f(a));' > %t/a.rs
+// RUN: printf 'n\ny\n' | mogglo-rust --confirm --recursive --replace 'g($x)' 'f($x)' %t/a.rs 2>&1 | FileCheck %s
+// RUN: cat %t/a.rs | FileCheck --check-prefix=REPLACED %s
+
+// CHECK-NOT: Skipping replacement
+// REPLACED: f(g(a));
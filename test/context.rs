@@ -0,0 +1,22 @@
+// RUN: mogglo-rust --only-matching --context 'match x { $PAT }' 'Some($x) => $y,' %s | FileCheck %s
+// RUN: mogglo-rust --only-matching --context 'match x { $PAT }' --kind match_arm '$p => $x' --where 'p == x' %s | FileCheck --check-prefix=KIND %s
+// RUN: mogglo-rust --only-matching --context 'struct S { $PAT }' --kind field_declaration --recursive --replace 'pub $f: $t' '$f: $t' --dry-run %s | uncom | FileCheck --check-prefix=REPLACE %s
+// RUN: (mogglo-rust --context 'match x { }' '$x' %s 2>&1 || true) | FileCheck --check-prefix=ERROR %s
+
+// CHECK: Some(a) => a,
+// CHECK-NOT: {{.+}}
+
+// KIND: None => None
+// KIND-NOT: {{.+}}
+
+// REPLACE: struct T { pub a: A, pub b: B }
+
+// ERROR: --context must contain $PAT
+fn f(x: Option<A>) -> Option<A> {
+    match x {
+        Some(a) => a,
+        None => None
+    }
+}
+
+struct T { a: A, b: B }
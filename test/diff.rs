@@ -0,0 +1,23 @@
+// RUN: mogglo-rust --diff --replace 'let $y = $x;' 'let $x = $y;' %s 2>&1 | FileCheck %s
+// RUN: rm -rf %t && mkdir -p %t && cp %s %t/a.rs
+// RUN: cd %t && mogglo-rust --diff --replace 'let $y = $x;' 'let $x = $y;' %t/a.rs > %t/a.diff
+// RUN: FileCheck --check-prefix=HEADER %s < %t/a.diff
+// RUN: cd %t && git apply -p1 a.diff && FileCheck --check-prefix=APPLIED %s < %t/a.rs
+// RUN: (mogglo-rust --diff 'let $x = $y;' %s 2>&1 || true) | FileCheck --check-prefix=REPLACE %s
+
+// CHECK: --- a/{{.*}}diff.rs
+// CHECK-NEXT: +++ b/{{.*}}diff.rs
+// CHECK-NEXT: @@ -{{.+}} @@
+// CHECK:  let a = a;
+// CHECK-NEXT: -let a = b;
+// CHECK-NEXT: -let c = d;
+// CHECK-NEXT: +let b = a;
+// CHECK-NEXT: +let d = c;
+// CHECK-NOT: {{.+}}
+// HEADER: --- a/a.rs
+// HEADER-NEXT: +++ b/a.rs
+// APPLIED: {{^}}let b = a;
+// REPLACE: error: the following required arguments were not provided:
+let a = a;
+let a = b;
+let c = d;
@@ -0,0 +1,33 @@
+// RUN: echo 'let x = f(1);' | mogglo-rust --dump-ast - | FileCheck %s
+// RUN: mogglo-rust --explain 'let $x:identifier = ${| $_ + $.. $| 0 |};' | FileCheck --check-prefix=EXPLAIN %s
+// RUN: mogglo-rust --explain --context 'match x { $PAT }' --kind match_arm '$p => $y' | FileCheck --check-prefix=CONTEXT %s
+
+// CHECK: source_file 0..14
+// CHECK-NEXT:   let_declaration 0..13
+// CHECK-NEXT:     "let" 0..3
+// CHECK-NEXT:     pattern: identifier 4..5 "x"
+// CHECK-NEXT:     "=" 6..7
+// CHECK-NEXT:     value: call_expression 8..12
+// CHECK-NEXT:       function: identifier 8..9 "f"
+// CHECK-NEXT:       arguments: arguments 9..12
+// CHECK-NEXT:         "(" 9..10
+// CHECK-NEXT:         integer_literal 10..11 "1"
+// CHECK-NEXT:         ")" 11..12
+// CHECK-NEXT:     ";" 12..13
+
+// EXPLAIN: Parsed: let mogglo_tmp_var_0 = mogglo_tmp_var_1;
+// EXPLAIN: pattern: identifier 4..20 "mogglo_tmp_var_0"  # $x:identifier
+// EXPLAIN: value: identifier 23..39 "mogglo_tmp_var_1"  # ${| ... |}
+// EXPLAIN: Alternative 1 for mogglo_tmp_var_1:
+// EXPLAIN-NEXT: Parsed: { mogglo_tmp_var_2 + mogglo_tmp_var_3 }
+// EXPLAIN: left: identifier 2..18 "mogglo_tmp_var_2"  # $_
+// EXPLAIN: right: identifier 21..37 "mogglo_tmp_var_3"  # $..
+// EXPLAIN: Alternative 2 for mogglo_tmp_var_1:
+// EXPLAIN-NEXT: Parsed: { 0 }
+// EXPLAIN-NEXT: Goal:
+// EXPLAIN-NEXT: integer_literal 2..3 "0"
+
+// CONTEXT: Parsed: match x { mogglo_tmp_var_0 => mogglo_tmp_var_1 }
+// CONTEXT-NEXT: Goal:
+// CONTEXT-NEXT: match_arm 10..46
+// CONTEXT-NEXT:   pattern: match_pattern 10..26  # $p
@@ -0,0 +1,6 @@
+// RUN: mogglo-rust --only-matching --dry-run --replace 'bar(ctx, $..args)' 'foo($..args)' %s | uncom | FileCheck %s
+// RUN: mogglo-rust --format jsonl 'foo($..args)' %s | FileCheck --check-prefix=JSON %s
+
+// CHECK: bar(ctx, a, b + c);
+// JSON: "bindings":{},"ellipses":{"args":{"range":{"start":{{[0-9]+}},"end":{{[0-9]+}}},{{.+}}"text":"a, b + c"}}
+foo(a, b + c);
@@ -0,0 +1,6 @@
+// RUN: mogglo-rust --only-matching 'fn $f() { $..; return $x; }' %s | FileCheck %s
+
+// CHECK: fn last() { let a = 0; return a; }
+// CHECK-NOT: {{.+}}
+fn last() { let a = 0; return a; }
+fn first() { return b; let c = 0; }
@@ -0,0 +1,4 @@
+// RUN: mogglo-rust --dry-run --only-matching --replace 'let $x = $buf[$i];' 'let $x = ${{rec_export("$buf.get($i)")}};' %s | uncom | FileCheck %s
+
+// CHECK: let a = buf[i];
+let a = buf.get(i).unwrap();
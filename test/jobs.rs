@@ -0,0 +1,18 @@
+// RUN: rm -rf %t && mkdir -p %t
+// RUN: echo 'let a = 1;' > %t/a.rs && echo 'let b = 2;' > %t/b.rs && echo 'let c = 3;' > %t/c.rs && echo 'let d = 4;' > %t/d.rs
+// RUN: mogglo-rust --jobs 4 --only-matching 'let $x = $y;' %t 2>&1 | FileCheck %s
+
+// CHECK: let a = 1;
+// CHECK-NEXT: let b = 2;
+// CHECK-NEXT: let c = 3;
+// CHECK-NEXT: let d = 4;
+
+// RUN: rm -rf %t && mkdir -p %t
+// RUN: echo 'let a = 1;' > %t/a.rs && echo 'let b = ;' > %t/b.rs && echo 'let c = 3;' > %t/c.rs && echo 'let d = 4;' > %t/d.rs
+// RUN: (mogglo-rust --jobs 4 --on-parse-error error --replace 'let $x = 0;' 'let $x = $y;' %t 2>&1 || true) | FileCheck --check-prefix=ERROR %s
+// RUN: cat %t/a.rs %t/c.rs %t/d.rs | FileCheck --check-prefix=WRITTEN %s
+
+// ERROR: [ERROR] Parse error in {{.+}}b.rs
+// WRITTEN: let a = 0;
+// WRITTEN-NEXT: let c = 3;
+// WRITTEN-NEXT: let d = 4;
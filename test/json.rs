@@ -0,0 +1,18 @@
+// RUN: mogglo-rust --format jsonl 'let $x = $y;' %s 2>&1 | FileCheck %s
+// RUN: mogglo-rust --format json --dry-run --replace 'let $y = $x;' 'let $x = $y;' %s 2>&1 | FileCheck --check-prefix=ARRAY %s
+// RUN: rm -rf %t && mkdir -p %t && echo 'let a = b;' > %t/a.rs
+// RUN: (echo n | mogglo-rust --confirm --format jsonl --replace 'let $y = $x;' 'let $x = $y;' %t/a.rs 2>&1 || true) | FileCheck --check-prefix=CONFIRM %s
+// RUN: cat %t/a.rs | FileCheck --check-prefix=UNCHANGED %s
+
+// CHECK: {"rule":"pattern-0","file":"{{.+}}json.rs","range":{"start":{{[0-9]+}},"end":{{[0-9]+}}},"start":{"line":17,"column":1},"end":{"line":17,"column":11},"text":"let a = b;","bindings":{"x":[{"range":{{.+}},"start":{"line":17,"column":5},"end":{"line":17,"column":6},"text":"a"}],"y":[{{.+}}"text":"b"}]},"ellipses":{},"replacement":null}
+
+// ARRAY: [
+// ARRAY-NEXT: {{.+}}"text":"let a = b;"{{.+}}"replacement":"let b = a;"},
+// ARRAY-NEXT: {{.+}}"text":"let c = d;"{{.+}}"replacement":"let d = c;"}
+// ARRAY-NEXT: ]
+
+// CONFIRM: error: the argument '--confirm' cannot be used with '--format <FORMAT>'
+// UNCHANGED: let a = b;
+
+let a = b;
+let c = d;
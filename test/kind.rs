@@ -0,0 +1,9 @@
+// RUN: mogglo-rust --only-matching 'let $x:identifier = $y:_literal;' %s | FileCheck %s
+
+// CHECK: let a = 0;
+// CHECK-NEXT: let c = "d";
+// CHECK-NOT: {{.+}}
+let a = 0;
+let b = a;
+let c = "d";
+let (e, f) = 1;
@@ -0,0 +1,24 @@
+// RUN: rm -rf %t && mkdir -p %t
+// RUN: echo 'let a = f(b);' > %t/a.rs && echo 'let c = f(d);' > %t/b.js && echo 'f(e)' > %t/c.txt
+// RUN: mogglo --only-matching 'f($x)' %t | FileCheck %s
+// RUN: mogglo --lang rust --only-matching 'f($x)' %t | FileCheck --check-prefix=RUST %s
+// RUN: echo 'f(g)' | mogglo --lang rust --only-matching 'f($x)' - | FileCheck --check-prefix=STDIN %s
+// RUN: (mogglo 'f($x)' %t/c.txt 2>&1 || true) | FileCheck --check-prefix=UNKNOWN %s
+// RUN: (mogglo --lang cobol 'f($x)' %t 2>&1 || true) | FileCheck --check-prefix=LANG %s
+// RUN: (mogglo 'let $x: $t = f($y);' %t 2>&1 || true) | FileCheck --check-prefix=ERROR %s
+
+// CHECK: f(b)
+// CHECK-NEXT: f(d)
+// CHECK-NOT: {{.+}}
+
+// RUST: f(b)
+// RUST-NOT: {{.+}}
+
+// STDIN: f(g)
+
+// UNKNOWN: can't tell the language of {{.*}}c.txt, pass --lang
+
+// LANG: unknown language cobol, expected one of:
+
+// ERROR: pattern (javascript)
+// ERROR: Pass --allow-pattern-errors to search anyway
@@ -0,0 +1,14 @@
+// RUN: mogglo-rust 'let $x = ${{match_export("$f(")}};' %s 2>&1 | FileCheck %s
+// RUN: mogglo-rust 'let $x = ${{not pcall(rec, "$f(")}};' %s 2>&1 | FileCheck --check-prefix=PCALL %s
+
+// CHECK: Failed to parse pattern in sub-pattern: $f(
+// CHECK-NOT: in sub-pattern
+// CHECK-NOT: Match
+
+// PCALL-NOT: in sub-pattern
+// PCALL: Match
+// PCALL: Match
+// PCALL-NOT: in sub-pattern
+
+let a = f(b);
+let c = f(d);
@@ -0,0 +1,26 @@
+// RUN: mogglo-rust --only-matching 'fn $f($..) { $.. }' --where 'nodes.f:parent():child_by_field_name("return_type") ~= nil' %s | uncom | FileCheck --check-prefix=FIELD %s
+// RUN: mogglo-rust --only-matching --dry-run --replace '${{nodes.f:field_name()}} ${{nodes.f:parent():named_child_count()}} ${{nodes.f:parent():field_name_for_child(1)}}' 'fn $f() {}' %s | uncom | FileCheck --check-prefix=NAMED %s
+// RUN: mogglo-rust --only-matching --dry-run --replace '${{nodes.f:start_point().row}}:${{nodes.f:end_point().column}}' 'fn $f() {}' %s | uncom | FileCheck --check-prefix=POINT %s
+// RUN: mogglo-rust --only-matching --dry-run --replace '${{local s, e = nodes.f:parent():byte_range(); return e - s}}' 'fn $f() {}' %s | uncom | FileCheck --check-prefix=RANGE %s
+// RUN: mogglo-rust --only-matching --dry-run --replace '${{nodes.f:parent():child_by_field_name("body"):to_sexp()}}' 'fn $f() {}' %s | uncom | FileCheck --check-prefix=SEXP %s
+// RUN: mogglo-rust --only-matching 'fn $f($..) { $.. }' --where 'local n = 0; for d in nodes.f:parent():descendants() do if d:kind() == "identifier" then n = n + 1 end end; return n == 3' %s | uncom | FileCheck --check-prefix=DESC %s
+// RUN: mogglo-rust --only-matching 'fn $f($..) { $.. }' --where 'local p = nodes.f:parent(); return p:is_named() and not p:is_error() and not p:has_error()' %s | uncom | FileCheck --check-prefix=VALID %s
+
+// FIELD: fn g(x: i32) -> i32 { x }
+// FIELD-NOT: fn h
+
+// NAMED: name 3 name
+
+// POINT: 25:4
+
+// RANGE: 9
+
+// SEXP: (block)
+
+// DESC: fn g(x: i32) -> i32 { x }
+// DESC-NOT: fn h() {}
+
+// VALID: fn g(x: i32) -> i32 { x }
+// VALID: fn h() {}
+fn g(x: i32) -> i32 { x }
+fn h() {}
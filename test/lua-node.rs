@@ -0,0 +1,9 @@
+// RUN: mogglo-rust --only-matching 'let $x = $y;' --where 'node("y"):kind() == "call_expression"' %s | FileCheck %s
+// RUN: mogglo-rust --only-matching --dry-run --replace 'let $x = ${{nodes.y:child(0):text()}};' 'let $x = $y;' --where 'nodes.y:child_count() > 0' %s | uncom | FileCheck --check-prefix=REPLACE %s
+
+// CHECK: let a = f(b);
+// CHECK-NOT: let c = d;
+// REPLACE: let a = f;
+// REPLACE: let c = d;
+let a = f(b);
+let c = d;
@@ -0,0 +1,13 @@
+// RUN: mogglo-rust --only-matching 'let $x = $y;' --where 'can_contain(node("y"):kind(), "expression_statement")' %s | uncom | FileCheck %s
+// RUN: mogglo-rust --only-matching 'let $x = $y;' --where 'fields_of("let_declaration").pattern.required and not fields_of("let_declaration").value.required' %s | uncom | FileCheck --check-prefix=FIELDS %s
+// RUN: mogglo-rust --only-matching 'let $x = $y;' --where 'allowed_in_field("let_declaration", "value")[1] == "_expression"' %s | uncom | FileCheck --check-prefix=ALLOWED %s
+
+// CHECK-NOT: let a
+// CHECK: let d = { e };
+
+// FIELDS: let a = b + c;
+// FIELDS: let d = { e };
+
+// ALLOWED: let a = b + c;
+let a = b + c;
+let d = { e };
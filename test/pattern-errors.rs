@@ -0,0 +1,23 @@
+// RUN: (mogglo-rust '$x:identfier + $y' %s 2>&1 || true) | FileCheck %s
+// RUN: (mogglo-rust '$x + $y' --where 'is_child_of(node("x"):kind(), "_expresion")' %s 2>&1 || true) | FileCheck --check-prefix=WHERE %s
+// RUN: (mogglo-rust 'let $x = $y = ;' %s 2>&1 || true) | FileCheck --check-prefix=SYNTAX %s
+// RUN: mogglo-rust --allow-pattern-errors --only-matching '$x:identfier + $y' %s 2>&1 | FileCheck --check-prefix=ALLOW %s
+// RUN: mogglo-rust --only-matching --replace '${{t}} ${{t}}' '$x + $y' --dry-run %s 2>&1 | uncom | FileCheck --check-prefix=REPLACE %s
+
+// CHECK: Unknown kind of node: identfier
+// CHECK: Did you mean `identifier`?
+// CHECK: Pass --allow-pattern-errors to search anyway
+
+// WHERE: Unknown kind of node: _expresion
+// WHERE: Did you mean `_expression`?
+
+// SYNTAX: Missing `identifier`
+
+// ALLOW: Unknown kind of node: identfier
+// ALLOW-NOT: Pass --allow-pattern-errors
+
+// REPLACE: Warning
+// REPLACE-NOT: Pass --allow-pattern-errors
+fn f() {
+    a + b;
+}
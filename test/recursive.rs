@@ -0,0 +1,8 @@
+// RUN: mogglo-rust --recursive --only-matching 'f($x)' %s | FileCheck %s
+
+// CHECK: f(f(a))
+// CHECK-NEXT: f(a)
+// CHECK-NOT: {{.+}}
+fn main() {
+    f(f(a));
+}
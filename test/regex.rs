@@ -0,0 +1,12 @@
+// RUN: mogglo-rust --only-matching '$f/^unsafe_/($.., $/^[0-9]+$/)' %s | FileCheck %s
+// RUN: mogglo-rust --only-matching '$x/2 + $y/3' %s | FileCheck --check-prefix=DIVISION %s
+
+// CHECK: unsafe_get(a, 1)
+// CHECK-NOT: {{.+}}
+unsafe_get(a, 1);
+unsafe_get(a, b);
+get(a, 1);
+
+// DIVISION: b/2 + c/3
+// DIVISION-NOT: {{.+}}
+let d = b/2 + c/3;
@@ -0,0 +1,33 @@
+// RUN: mogglo-rust --recursive --only-matching '$x.unwrap()' --not-inside '#[test] fn $f() { $.. }' %s | FileCheck %s
+// RUN: mogglo-rust --recursive --only-matching '$x.unwrap()' --inside 'if $x.is_some() { $.. }' %s | FileCheck --check-prefix=INSIDE %s
+// RUN: mogglo-rust --only-matching 'fn $f() { $.. }' --has 'e()' --not-has '$_.unwrap()' %s | FileCheck --check-prefix=HAS %s
+
+// CHECK: a.unwrap()
+// CHECK-NEXT: c.unwrap()
+// CHECK-NEXT: d.unwrap()
+// CHECK-NEXT: e().unwrap()
+// CHECK-NOT: {{.+}}
+fn f() {
+    a.unwrap();
+}
+
+#[test]
+fn g() {
+    b.unwrap();
+}
+
+// INSIDE: c.unwrap()
+// INSIDE-NOT: {{.+}}
+fn h() {
+    if c.is_some() {
+        c.unwrap();
+    }
+    if c.is_some() {
+        d.unwrap();
+    }
+}
+
+// HAS: fn i() { unsafe { e() } }
+// HAS-NOT: {{.+}}
+fn i() { unsafe { e() } }
+fn j() { unsafe { e().unwrap() } }
@@ -0,0 +1,8 @@
+// RUN: mogglo-rust --dry-run --only-matching --replace 'f($x);' 'let $y = $x;' %s 2>&1 | uncom | FileCheck %s
+
+// CHECK: f(b)
+// CHECK-NEXT: let c = d;
+// CHECK: f(b)
+// CHECK-NEXT: f(d)
+let aaaa = b;
+let c = d;
@@ -0,0 +1,13 @@
+// RUN: mogglo-rust 'let $x = $x;' %s | FileCheck %s
+// RUN: mogglo-rust --dry-run --replace 'let $x = ();' 'let $x = $x;' %s | FileCheck --check-prefix=REPLACE %s
+
+// CHECK: {{.*}}Match{{.*}}
+// CHECK: {{.*}}report.rs:12:{{.*}}
+// CHECK-NOT: report.rs:13:
+
+// REPLACE: {{.*}}Would replace{{.*}}
+// REPLACE: {{.*}}report.rs:12:{{.*}}
+// REPLACE: {{.*}}With{{.*}}
+// REPLACE: {{.*}}report.rs:12:{{.*}}
+let a = a;
+let b = c;
@@ -0,0 +1,20 @@
+// RUN: mogglo test %S/rules | FileCheck --check-prefix=PASS %s
+// RUN: (mogglo-rust test %S/rule-test/bad.toml || true) | FileCheck --check-prefix=FAIL %s
+
+// PASS: [PASS] python-self-assign
+// PASS-NEXT: [PASS] self-assign
+// PASS-NEXT: [PASS] unit
+// PASS-NEXT: 3 passed, 0 failed
+
+// FAIL: [FAIL] unit
+// FAIL-NEXT:   valid example 1 matched:
+// FAIL-NEXT:   let a = ();
+// FAIL-NEXT:   invalid example 1 didn't match:
+// FAIL-NEXT:   let a = b;
+// FAIL-NEXT:   wrong fix for invalid example 2:
+// FAIL-NEXT:   --- expected
+// FAIL-NEXT:   +++ actual
+// FAIL-NEXT:   @@ -1 +1 @@
+// FAIL-NEXT:   -let a = 0;
+// FAIL-NEXT:   +let a = 1;
+// FAIL-NEXT: 0 passed, 1 failed
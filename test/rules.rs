@@ -0,0 +1,16 @@
+// RUN: mogglo --lang rust --rules %S/rules --format jsonl --dry-run %s 2>&1 | FileCheck %s
+// RUN: (mogglo-rust --rules %S/rules %s 2>&1 || true) | FileCheck --check-prefix=UNAVAILABLE %s
+// RUN: rm -rf %t && mkdir -p %t && printf '[[rules]]\nid = "a"\nlanguage = "rsut"\npattern = "a"\nmessage = "a"\n' > %t/a.toml
+// RUN: (mogglo-rust --rules %t/a.toml %s 2>&1 || true) | FileCheck --check-prefix=LANG %s
+// RUN: printf '[[rules]]\nid = "a"\nlanguage = "rust"\npattern = "a"\nreplacement = "b"\nmessage = "a"\n' > %t/b.toml
+// RUN: (mogglo-rust --rules %t/b.toml %s 2>&1 || true) | FileCheck --check-prefix=FIELD %s
+
+// CHECK: {"rule":"self-assign",{{.+}}"text":"let a = a;"{{.+}}"replacement":null}
+// CHECK-NEXT: {"rule":"unit",{{.+}}"text":"let b = ();"{{.+}}"replacement":"let b = 0;"}
+// CHECK-NOT: {{.+}}
+// LANG: Unknown language rsut in rule file {{.+}}a.toml
+// FIELD: unknown field `replacement`
+// UNAVAILABLE: Unknown language python in rule file {{.+}}self-assign.toml, expected one of: rust
+let a = a;
+let b = ();
+let c = d;
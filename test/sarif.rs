@@ -0,0 +1,36 @@
+// RUN: mogglo-rust --format sarif --dry-run --replace '()' 'let $x = $x;' %s 2>&1 | FileCheck %s
+// RUN: cd %S && mogglo-rust --format sarif 'let $x = $x;' sarif.rs | FileCheck --check-prefix=RELATIVE %s
+// RUN: rm -rf %t && mkdir -p %t && echo 'let a = a;' > %t/a.rs && echo 'let b = b;' > %t/b.js
+// RUN: printf '[[rules]]\nid = "x"\nlanguage = "rust"\npattern = "let $x = $x;"\nmessage = "x"\n[[rules]]\nid = "x"\nlanguage = "javascript"\npattern = "let $x = $x;"\nmessage = "x"\n' > %t/rules.toml
+// RUN: mogglo --format sarif --rules %t/rules.toml %t | FileCheck --check-prefix=RULES %s
+// RUN: printf '[[rules]]\nid = "x"\nlanguage = "rust"\npattern = "let $x = $x;"\nmessage = "x"\n[[rules]]\nid = "x"\nlanguage = "javascript"\npattern = "let $x = $x;"\nmessage = "y"\n' > %t/conflict.toml
+// RUN: (mogglo --format sarif --rules %t/conflict.toml %t 2>&1 || true) | FileCheck --check-prefix=CONFLICT %s
+
+// CHECK: "$schema": "https://json.schemastore.org/sarif-2.1.0.json",
+// CHECK: "results": [
+// CHECK: "insertedContent": {
+// CHECK-NEXT: "text": "()"
+// CHECK: "uri": "file:///{{.+}}/sarif.rs"
+// CHECK: "startLine": 36
+// CHECK: "message": {
+// CHECK-NEXT: "text": "let $x = $x;"
+// CHECK: "ruleId": "pattern-0"
+// CHECK: "name": "mogglo",
+// CHECK: "id": "pattern-0"
+// CHECK-NEXT: "shortDescription": {
+// CHECK-NEXT: "text": "let $x = $x;"
+// CHECK: "version": "2.1.0"
+
+// RELATIVE: "%SRCROOT%": {
+// RELATIVE-NEXT: "uri": "file:///{{.+}}/"
+// RELATIVE: "uri": "sarif.rs",
+// RELATIVE-NEXT: "uriBaseId": "%SRCROOT%"
+
+// RULES: "rules": [
+// RULES: "id": "x"
+// RULES-NOT: "id": "x"
+// RULES: "version"
+
+// CONFLICT: Rules with the id x have different messages or severities
+
+let a = a;
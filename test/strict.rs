@@ -0,0 +1,8 @@
+// RUN: mogglo-rust --strict --only-matching 'fn $f() { $x; $y; }' %s | FileCheck %s
+
+// CHECK: fn two() { a; b; }
+// CHECK-NEXT: fn commented() { a; /* c */ b; }
+// CHECK-NOT: {{.+}}
+fn two() { a; b; }
+fn three() { a; b; c; }
+fn commented() { a; /* c */ b; }
@@ -0,0 +1,23 @@
+// RUN: mogglo-rust --trace 21:13 'g($x, 3)' %s | FileCheck %s
+// RUN: mogglo-rust --trace 22 '$f($y, $y)' %s | FileCheck --check-prefix=METAVAR %s
+// RUN: mogglo-rust --trace 22 '$f($..)' --where 'f == "g"' %s | FileCheck --check-prefix=WHERE %s
+// RUN: mogglo-rust --trace 21 'let $x = g($..);' %s | FileCheck --check-prefix=MATCH %s
+
+// CHECK: `($x, 3)` ~ `(1, 2)`
+// CHECK-NEXT: `(` ~ `(`
+// CHECK-NEXT: `$x` ~ `1`
+// CHECK-NEXT: `,` ~ `,`
+// CHECK-NEXT: `3` ~ `2` {{[0-9]+}}..{{[0-9]+}}: Different text
+// CHECK: Different text
+// CHECK: Compared to `3`
+
+// METAVAR: `$y` ~ `b` {{[0-9]+}}..{{[0-9]+}}: Not equal to the earlier occurrence of $y
+// METAVAR: $y was bound here
+
+// WHERE: Where clause was not true: f == "g"
+
+// MATCH: Matched let_declaration
+fn f() {
+    let x = g(1, 2);
+    h(a, b);
+}
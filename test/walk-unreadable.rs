@@ -0,0 +1,10 @@
+// RUN: (chmod -R u+rwx %t || true) && rm -rf %t && mkdir -p %t/a %t/b
+// RUN: uncom < %s > %t/a/a.rs && uncom < %s > %t/b/b.rs && chmod 000 %t/a
+// RUN: mogglo-rust --only-matching 'let $x = $x;' %t > %t.out 2>&1; chmod 755 %t/a
+// RUN: FileCheck %s < %t.out
+
+// Unreadable directories are skipped with a warning (unless running as root,
+// who can read them anyway), the rest is still searched.
+
+// CHECK: let a = a;
+let a = a;
@@ -0,0 +1,17 @@
+// RUN: rm -rf %t && mkdir -p %t/src %t/target %t/.hidden
+// RUN: uncom < %s > %t/src/a.rs && uncom < %s > %t/target/b.rs && uncom < %s > %t/.hidden/c.rs && uncom < %s > %t/src/d.txt
+// RUN: echo target > %t/.ignore
+// RUN: mogglo-rust --only-matching 'let $x = $x;' %t 2>&1 | FileCheck %s
+// RUN: mogglo-rust --hidden --no-ignore --only-matching 'let $x = $x;' %t 2>&1 | FileCheck --check-prefix=ALL %s
+
+// CHECK: let a = a;
+// CHECK-EMPTY:
+// CHECK-NOT: {{.+}}
+
+// ALL: let a = a;
+// ALL: let a = a;
+// ALL: let a = a;
+// ALL-EMPTY:
+// ALL-NOT: {{.+}}
+let a = a;
+let a = b;